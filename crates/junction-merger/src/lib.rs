@@ -0,0 +1,737 @@
+//! Merging of configuration files fetched from several Junction sources.
+//!
+//! Every merger takes the sources in order, later sources overriding earlier
+//! ones, and returns the merged file as a string.
+
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("All JSON sources must be objects")]
+    JsonNotObject,
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSerialize(#[from] toml::ser::Error),
+    #[error("All TOML sources must be tables")]
+    TomlNotTable,
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("All YAML sources must be mappings")]
+    YamlNotMapping,
+    #[error("YAML sources must contain a single document")]
+    YamlMultipleDocuments,
+    #[error("Environment variable {0} is not defined")]
+    UndefinedVariable(String),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("CSV source {0} has columns missing from the first header: {1}")]
+    CsvExtraColumns(usize, String),
+}
+
+/// How deep merging combines two arrays found at the same key.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ArrayStrategy {
+    /// The later array replaces the earlier one.
+    Replace,
+    /// The later array is appended to the earlier one.
+    Concat,
+}
+
+/// Merges the JSON objects in `contents`, later sources overriding earlier
+/// ones. With `deep` set, nested objects are merged with [`deep_merge_json`].
+///
+/// ```
+/// use junction_merger::merge_json_contents;
+///
+/// let merged = merge_json_contents(
+///     vec![r#"{"a": 1, "b": 2}"#.to_string(), r#"{"b": 3}"#.to_string()],
+///     None,
+/// )
+/// .unwrap();
+/// assert_eq!(merged, "{\n  \"a\": 1,\n  \"b\": 3\n}");
+/// ```
+pub fn merge_json_contents(
+    contents: Vec<String>,
+    deep: Option<ArrayStrategy>,
+) -> Result<String, MergeError> {
+    let mut merged_object = serde_json::Map::new();
+
+    for content in contents {
+        let value: Value = serde_json::from_str(&content)?;
+
+        if let Value::Object(obj) = value {
+            for (key, val) in obj {
+                match (deep, merged_object.get_mut(&key)) {
+                    (Some(array_strategy), Some(existing)) => {
+                        deep_merge_json(existing, val, array_strategy)
+                    }
+                    _ => {
+                        merged_object.insert(key, val);
+                    }
+                }
+            }
+        } else {
+            return Err(MergeError::JsonNotObject);
+        }
+    }
+
+    Ok(serde_json::to_string_pretty(&Value::Object(merged_object))?)
+}
+
+/// Merges `b` into `a`. Objects are merged key by key, arrays according to
+/// `array_strategy`, and any other value in `b` replaces the one in `a`.
+///
+/// ```
+/// use junction_merger::deep_merge_json;
+/// use junction_merger::ArrayStrategy;
+/// use serde_json::json;
+///
+/// let mut a = json!({"db": {"host": "a"}, "tags": ["x"]});
+/// deep_merge_json(
+///     &mut a,
+///     json!({"db": {"port": 5432}, "tags": ["y"]}),
+///     ArrayStrategy::Concat,
+/// );
+/// assert_eq!(a, json!({"db": {"host": "a", "port": 5432}, "tags": ["x", "y"]}));
+/// ```
+pub fn deep_merge_json(a: &mut Value, b: Value, array_strategy: ArrayStrategy) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, val) in b {
+                match a.get_mut(&key) {
+                    Some(existing) => deep_merge_json(existing, val, array_strategy),
+                    None => {
+                        a.insert(key, val);
+                    }
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if matches!(array_strategy, ArrayStrategy::Concat) => {
+            a.extend(b);
+        }
+        (a, b) => *a = b,
+    }
+}
+
+/// Merges the top-level tables in `contents`, later sources overriding
+/// earlier ones.
+///
+/// ```
+/// use junction_merger::merge_toml_contents;
+///
+/// let merged = merge_toml_contents(vec![
+///     "name = \"base\"\nport = 80\n".to_string(),
+///     "port = 8080\n".to_string(),
+/// ])
+/// .unwrap();
+/// assert_eq!(merged, "name = \"base\"\nport = 8080\n");
+/// ```
+pub fn merge_toml_contents(contents: Vec<String>) -> Result<String, MergeError> {
+    let mut merged_table = toml::Table::new();
+
+    for content in contents {
+        // A bare value such as `42` is not a document, but parses as a value
+        let table = match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => match content.parse::<toml::Value>() {
+                Ok(toml::Value::Table(table)) => table,
+                Ok(_) => return Err(MergeError::TomlNotTable),
+                Err(_) => return Err(e.into()),
+            },
+        };
+
+        for (key, val) in table {
+            merged_table.insert(key, val);
+        }
+    }
+
+    Ok(toml::to_string_pretty(&merged_table)?)
+}
+
+/// Merges the top-level mappings in `contents`, later sources overriding
+/// earlier ones. Each source must hold exactly one YAML document; files with
+/// several `---` separated documents are rejected rather than silently
+/// truncated.
+///
+/// ```
+/// use junction_merger::merge_yaml_contents;
+///
+/// let merged = merge_yaml_contents(vec![
+///     "name: base\nport: 80\n".to_string(),
+///     "port: 8080\n".to_string(),
+/// ])
+/// .unwrap();
+/// assert_eq!(merged, "name: base\nport: 8080\n");
+/// ```
+pub fn merge_yaml_contents(contents: Vec<String>) -> Result<String, MergeError> {
+    let mut merged_mapping = serde_yaml::Mapping::new();
+
+    for content in contents {
+        let mut documents = serde_yaml::Deserializer::from_str(&content);
+        let value = match documents.next() {
+            Some(document) => serde_yaml::Value::deserialize(document)?,
+            None => serde_yaml::Value::Null,
+        };
+        if documents.next().is_some() {
+            return Err(MergeError::YamlMultipleDocuments);
+        }
+
+        if let serde_yaml::Value::Mapping(mapping) = value {
+            for (key, val) in mapping {
+                merged_mapping.insert(key, val);
+            }
+        } else {
+            return Err(MergeError::YamlNotMapping);
+        }
+    }
+
+    Ok(serde_yaml::to_string(&serde_yaml::Value::Mapping(
+        merged_mapping,
+    ))?)
+}
+
+/// Concatenates `contents`, one source per line.
+///
+/// ```
+/// use junction_merger::merge_plaintext_contents;
+///
+/// let merged = merge_plaintext_contents(vec!["a".to_string(), "b".to_string()]);
+/// assert_eq!(merged, "a\nb");
+/// ```
+pub fn merge_plaintext_contents(contents: Vec<String>) -> String {
+    contents.join("\n")
+}
+
+/// Removes repeated lines, keeping the first occurrence, and/or sorts lines
+/// lexicographically. Blank lines and `#` comments are never removed or
+/// moved: with `sort`, only the other lines are sorted, into the positions
+/// such lines already held.
+///
+/// ```
+/// use junction_merger::dedup_and_sort_lines;
+///
+/// let tidied = dedup_and_sort_lines("# hosts\nb\na\n\nb\n", true, true);
+/// assert_eq!(tidied, "# hosts\na\nb\n\n");
+/// ```
+pub fn dedup_and_sort_lines(content: &str, dedup: bool, sort: bool) -> String {
+    let is_entry = |line: &str| !line.trim().is_empty() && !line.trim_start().starts_with('#');
+
+    let mut seen = HashSet::new();
+    let mut lines: Vec<&str> = content
+        .split('\n')
+        .filter(|line| !dedup || !is_entry(line) || seen.insert(*line))
+        .collect();
+
+    if sort {
+        let mut entries: Vec<&str> = lines
+            .iter()
+            .copied()
+            .filter(|line| is_entry(line))
+            .collect();
+        entries.sort_unstable();
+        let mut entries = entries.into_iter();
+        for line in lines.iter_mut().filter(|line| is_entry(line)) {
+            *line = entries.next().expect("one entry per entry slot");
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// `${VAR}` or `${VAR:-default}`, capturing the name and the default.
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").expect("valid regex")
+});
+
+/// Replaces `${VAR}` and `${VAR:-default}` placeholders with values from
+/// `lookup`. A variable that is not defined and has no default is an error.
+///
+/// ```
+/// use junction_merger::interpolate_env_vars;
+///
+/// let lookup = |name: &str| (name == "REGION").then(|| "eu-west-1".to_string());
+/// assert_eq!(
+///     interpolate_env_vars("${REGION}/${ZONE:-a}", lookup).unwrap(),
+///     "eu-west-1/a"
+/// );
+/// ```
+pub fn interpolate_env_vars(
+    value: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, MergeError> {
+    let mut output = String::new();
+    let mut last_end = 0;
+    for captures in PLACEHOLDER.captures_iter(value) {
+        let whole = captures.get(0).unwrap();
+        let name = &captures[1];
+        let replacement = match (lookup(name), captures.get(2)) {
+            (Some(var), _) => var,
+            (None, Some(default)) => default.as_str().to_string(),
+            (None, None) => return Err(MergeError::UndefinedVariable(name.to_string())),
+        };
+
+        output.push_str(&value[last_end..whole.start()]);
+        output.push_str(&replacement);
+        last_end = whole.end();
+    }
+    output.push_str(&value[last_end..]);
+
+    Ok(output)
+}
+
+/// Merges CSV files under the header of the first one. Rows of later sources
+/// are reordered to match it by column name, and columns they lack are left
+/// empty. Columns the first header lacks are an error, or dropped with
+/// `ignore_extra`.
+///
+/// ```
+/// use junction_merger::merge_csv_contents;
+///
+/// let merged = merge_csv_contents(
+///     vec![
+///         "name,port\na,80\n".to_string(),
+///         "port,name\n8080,b\n".to_string(),
+///     ],
+///     false,
+/// )
+/// .unwrap();
+/// assert_eq!(merged, "name,port\na,80\nb,8080\n");
+/// ```
+pub fn merge_csv_contents(contents: Vec<String>, ignore_extra: bool) -> Result<String, MergeError> {
+    let mut contents = contents.iter();
+    let Some(first) = contents.next() else {
+        return Ok(String::new());
+    };
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut reader = csv::Reader::from_reader(first.as_bytes());
+    let header = reader.headers()?.clone();
+    writer.write_record(&header)?;
+    for record in reader.records() {
+        writer.write_record(&record?)?;
+    }
+
+    for (i, content) in contents.enumerate() {
+        let mut reader = csv::Reader::from_reader(content.as_bytes());
+        let source_header = reader.headers()?.clone();
+        let extra: Vec<&str> = source_header
+            .iter()
+            .filter(|column| !header.iter().any(|name| name == *column))
+            .collect();
+        if !extra.is_empty() && !ignore_extra {
+            return Err(MergeError::CsvExtraColumns(i + 2, extra.join(", ")));
+        }
+
+        // Position of each canonical column in this source, if it has it
+        let positions: Vec<Option<usize>> = header
+            .iter()
+            .map(|name| source_header.iter().position(|column| column == name))
+            .collect();
+        for record in reader.records() {
+            let record = record?;
+            writer.write_record(
+                positions
+                    .iter()
+                    .map(|position| position.and_then(|i| record.get(i)).unwrap_or_default()),
+            )?;
+        }
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))?;
+    Ok(String::from_utf8(bytes).expect("CSV written from strings is UTF-8"))
+}
+
+/// A section of a merged INI file, with the comments above its header.
+#[derive(Default)]
+struct IniSection {
+    comments: Vec<String>,
+    entries: IndexMap<String, IniEntry>,
+}
+
+/// A key of a merged INI file, with the comments above it.
+struct IniEntry {
+    comments: Vec<String>,
+    value: Option<String>,
+}
+
+/// Replaces `comments` with `pending` unless there are none pending, so a
+/// later source without comments keeps the earlier ones.
+fn take_comments(comments: &mut Vec<String>, pending: &mut Vec<String>) {
+    if !pending.is_empty() {
+        *comments = std::mem::take(pending);
+    }
+}
+
+/// Merges INI files section by section, later keys overriding earlier ones.
+/// Case and order of sections and keys are kept. With `interpolate_env`,
+/// values go through [`interpolate_env_vars`] with the process environment.
+///
+/// Comment lines are kept with the section or key that follows them, and a
+/// later source's comments for the same section or key replace the earlier
+/// ones. Comments after the last key of a source are dropped.
+///
+/// ```
+/// use junction_merger::merge_ini_contents;
+///
+/// let merged = merge_ini_contents(
+///     vec![
+///         "[db]\nHost=a\nport=5432\n".to_string(),
+///         "[db]\nHost=b\n".to_string(),
+///     ],
+///     false,
+/// )
+/// .unwrap();
+/// assert_eq!(merged, "[db]\nHost=b\nport=5432\n\n");
+/// ```
+pub fn merge_ini_contents(
+    contents: Vec<String>,
+    interpolate_env: bool,
+) -> Result<String, MergeError> {
+    let mut merged_map: IndexMap<String, IniSection> = IndexMap::new();
+
+    for content in contents {
+        // Parse INI content manually to preserve case
+        let mut current_section = String::new();
+        let mut comments = Vec::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with(';') || line.starts_with('#') {
+                comments.push(line.to_string());
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                // Section header
+                current_section = line[1..line.len() - 1].to_string();
+                let section = merged_map.entry(current_section.clone()).or_default();
+                take_comments(&mut section.comments, &mut comments);
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                // Key-value pair
+                Some(eq_pos) => {
+                    let mut value = line[eq_pos + 1..].trim().to_string();
+                    if interpolate_env {
+                        value = interpolate_env_vars(&value, |name| std::env::var(name).ok())?;
+                    }
+                    let value = if value.is_empty() { None } else { Some(value) };
+                    (line[..eq_pos].trim().to_string(), value)
+                }
+                // Key without value
+                None => (line.to_string(), None),
+            };
+            let section = merged_map.entry(current_section.clone()).or_default();
+            let entry = section.entries.entry(key).or_insert(IniEntry {
+                comments: Vec::new(),
+                value: None,
+            });
+            entry.value = value;
+            take_comments(&mut entry.comments, &mut comments);
+        }
+    }
+
+    // Convert back to INI format
+    let mut output = String::new();
+
+    for (section_name, section) in merged_map {
+        for comment in &section.comments {
+            output.push_str(&format!("{comment}\n"));
+        }
+        if !section_name.is_empty() {
+            output.push_str(&format!("[{section_name}]\n"));
+        }
+
+        for (key, entry) in section.entries {
+            for comment in &entry.comments {
+                output.push_str(&format!("{comment}\n"));
+            }
+            match entry.value {
+                Some(val) => output.push_str(&format!("{key}={val}\n")),
+                None => output.push_str(&format!("{key}\n")),
+            }
+        }
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deep_merge_json_nested_objects() {
+        let mut a = serde_json::json!({"db": {"host": "a", "pool": {"min": 1, "max": 5}}});
+        let b = serde_json::json!({"db": {"pool": {"max": 10}, "user": "app"}});
+
+        deep_merge_json(&mut a, b, ArrayStrategy::Replace);
+        assert_eq!(
+            a,
+            serde_json::json!({"db": {"host": "a", "pool": {"min": 1, "max": 10}, "user": "app"}})
+        );
+    }
+
+    #[test]
+    fn test_deep_merge_json_arrays() {
+        let mut a = serde_json::json!({"tags": ["a", "b"]});
+        deep_merge_json(
+            &mut a,
+            serde_json::json!({"tags": ["c"]}),
+            ArrayStrategy::Concat,
+        );
+        assert_eq!(a, serde_json::json!({"tags": ["a", "b", "c"]}));
+
+        deep_merge_json(
+            &mut a,
+            serde_json::json!({"tags": ["d"]}),
+            ArrayStrategy::Replace,
+        );
+        assert_eq!(a, serde_json::json!({"tags": ["d"]}));
+    }
+
+    #[test]
+    fn test_deep_merge_json_scalar_override() {
+        let mut a = serde_json::json!({"port": 80, "debug": {"level": 1}});
+        let b = serde_json::json!({"port": 8080, "debug": false});
+
+        deep_merge_json(&mut a, b, ArrayStrategy::Replace);
+        assert_eq!(a, serde_json::json!({"port": 8080, "debug": false}));
+    }
+
+    #[test]
+    fn test_merge_json_contents_deep() {
+        let contents = vec![
+            r#"{"a": {"x": 1}, "b": [1]}"#.to_string(),
+            r#"{"a": {"y": 2}, "b": [2]}"#.to_string(),
+        ];
+
+        let flat: Value =
+            serde_json::from_str(&merge_json_contents(contents.clone(), None).unwrap()).unwrap();
+        assert_eq!(flat, serde_json::json!({"a": {"y": 2}, "b": [2]}));
+
+        let deep: Value = serde_json::from_str(
+            &merge_json_contents(contents, Some(ArrayStrategy::Concat)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            deep,
+            serde_json::json!({"a": {"x": 1, "y": 2}, "b": [1, 2]})
+        );
+    }
+
+    #[test]
+    fn test_merge_toml_contents_override() {
+        let merged = merge_toml_contents(vec![
+            "name = \"base\"\nport = 80\n\n[server]\nhost = \"a\"\n".to_string(),
+            "port = 8080\n\n[server]\nworkers = 4\n".to_string(),
+        ])
+        .unwrap();
+
+        let merged: toml::Table = merged.parse().unwrap();
+        assert_eq!(merged["name"].as_str(), Some("base"));
+        assert_eq!(merged["port"].as_integer(), Some(8080));
+        // Merging is shallow, so the later table replaces the earlier one
+        let server = merged["server"].as_table().unwrap();
+        assert_eq!(server.get("host"), None);
+        assert_eq!(server["workers"].as_integer(), Some(4));
+    }
+
+    #[test]
+    fn test_merge_toml_contents_non_table() {
+        let err = merge_toml_contents(vec!["a = 1".to_string(), "42".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "All TOML sources must be tables");
+    }
+
+    #[test]
+    fn test_merge_yaml_contents_override() {
+        let merged = merge_yaml_contents(vec![
+            "name: base\nport: 80\nserver:\n  host: a\n".to_string(),
+            "port: 8080\nserver:\n  workers: 4\n".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(merged, "name: base\nport: 8080\nserver:\n  workers: 4\n");
+    }
+
+    #[test]
+    fn test_merge_yaml_contents_invalid_documents() {
+        let err = merge_yaml_contents(vec!["a: 1\n---\nb: 2\n".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "YAML sources must contain a single document"
+        );
+
+        let err = merge_yaml_contents(vec!["- a\n- b\n".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "All YAML sources must be mappings");
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "REGION" => Some("eu-west-1".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate_env_vars_resolved() {
+        assert_eq!(
+            interpolate_env_vars("s3.${REGION}.amazonaws.com", lookup).unwrap(),
+            "s3.eu-west-1.amazonaws.com"
+        );
+        assert_eq!(
+            interpolate_env_vars("${REGION:-us-east-1}/${EMPTY:-unused}", lookup).unwrap(),
+            "eu-west-1/"
+        );
+        assert_eq!(
+            interpolate_env_vars("no placeholders", lookup).unwrap(),
+            "no placeholders"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_env_vars_defaulted() {
+        assert_eq!(
+            interpolate_env_vars("${MISSING:-fallback}", lookup).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            interpolate_env_vars("[${MISSING:-}]", lookup).unwrap(),
+            "[]"
+        );
+
+        let err = interpolate_env_vars("${MISSING}", lookup).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Environment variable MISSING is not defined"
+        );
+    }
+
+    #[test]
+    fn test_dedup_and_sort_lines_dedup() {
+        let merged = merge_plaintext_contents(vec![
+            "# first\nb.example\na.example\n".to_string(),
+            "# second\na.example\n\nc.example\n# first\n".to_string(),
+        ]);
+        assert_eq!(
+            dedup_and_sort_lines(&merged, true, false),
+            "# first\nb.example\na.example\n\n# second\n\nc.example\n# first\n"
+        );
+    }
+
+    #[test]
+    fn test_dedup_and_sort_lines_sort() {
+        let merged = merge_plaintext_contents(vec![
+            "c.example\n# comment\nb.example".to_string(),
+            "a.example\nb.example".to_string(),
+        ]);
+        assert_eq!(
+            dedup_and_sort_lines(&merged, false, true),
+            "a.example\n# comment\nb.example\nb.example\nc.example"
+        );
+    }
+
+    #[test]
+    fn test_dedup_and_sort_lines_both() {
+        let merged = merge_plaintext_contents(vec![
+            "c.example\n# comment\nb.example".to_string(),
+            "a.example\nb.example\n".to_string(),
+        ]);
+        assert_eq!(
+            dedup_and_sort_lines(&merged, true, true),
+            "a.example\n# comment\nb.example\nc.example\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_csv_contents_reordered_columns() {
+        let contents = vec![
+            "id,name,port\n1,a,80\n".to_string(),
+            "port,id,name\n8080,2,\"b, c\"\n".to_string(),
+            "name,id\nd,3\n".to_string(),
+        ];
+
+        let merged = merge_csv_contents(contents, false).unwrap();
+        assert_eq!(merged, "id,name,port\n1,a,80\n2,\"b, c\",8080\n3,d,\n");
+    }
+
+    #[test]
+    fn test_merge_csv_contents_mismatched_headers() {
+        let contents = vec![
+            "id,name\n1,a\n".to_string(),
+            "id,name,owner,team\n2,b,bob,core\n".to_string(),
+        ];
+
+        let err = merge_csv_contents(contents.clone(), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "CSV source 2 has columns missing from the first header: owner, team"
+        );
+
+        let merged = merge_csv_contents(contents, true).unwrap();
+        assert_eq!(merged, "id,name\n1,a\n2,b\n");
+    }
+
+    #[test]
+    fn test_merge_ini_contents_keeps_comments() {
+        let contents = vec![
+            "; Database settings\n[db]\n# Primary host\nhost=a\nport=5432\n".to_string(),
+            "[db]\nport=6432\n; Added later\nuser=admin\n".to_string(),
+        ];
+
+        let merged = merge_ini_contents(contents, false).unwrap();
+        assert_eq!(
+            merged,
+            "; Database settings\n[db]\n# Primary host\nhost=a\nport=6432\n; Added later\nuser=admin\n\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_ini_contents_later_comment_replaces() {
+        let contents = vec![
+            "[db]\n# Old comment\nhost=a\n".to_string(),
+            "[db]\n# New comment\n; spanning two lines\nhost=b\n".to_string(),
+        ];
+
+        let merged = merge_ini_contents(contents, false).unwrap();
+        assert_eq!(
+            merged,
+            "[db]\n# New comment\n; spanning two lines\nhost=b\n\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_ini_contents_interpolate_env() {
+        let contents = vec![
+            "[db]\nhost=${JUNCTION_MERGER_TEST_UNSET_VAR:-localhost}\nport=5432\n".to_string(),
+            "[db]\nuser=${JUNCTION_MERGER_TEST_UNSET_VAR:-admin}\n".to_string(),
+        ];
+
+        let merged = merge_ini_contents(contents.clone(), true).unwrap();
+        assert_eq!(merged, "[db]\nhost=localhost\nport=5432\nuser=admin\n\n");
+
+        let merged = merge_ini_contents(contents, false).unwrap();
+        assert!(merged.contains("host=${JUNCTION_MERGER_TEST_UNSET_VAR:-localhost}"));
+
+        let undefined = vec!["key=${JUNCTION_MERGER_TEST_UNSET_VAR}".to_string()];
+        assert!(merge_ini_contents(undefined, true).is_err());
+    }
+}
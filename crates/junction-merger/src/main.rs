@@ -1,11 +1,26 @@
 use std::io::Write;
 use std::io::{self};
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use clap::Arg;
 use clap::ArgAction;
+use clap::ArgGroup;
 use clap::Command;
 use clap::ValueEnum;
+use futures_util::StreamExt;
+use futures_util::TryStreamExt;
+use junction_merger::dedup_and_sort_lines;
+use junction_merger::merge_csv_contents;
+use junction_merger::merge_ini_contents;
+use junction_merger::merge_json_contents;
+use junction_merger::merge_plaintext_contents;
+use junction_merger::merge_toml_contents;
+use junction_merger::merge_yaml_contents;
+use junction_merger::ArrayStrategy;
+use regex::Regex;
 use serde_json::Value;
 use tracing_subscriber::prelude::*;
 
@@ -14,6 +29,9 @@ enum MergeType {
     Json,
     Plaintext,
     Ini,
+    Toml,
+    Yaml,
+    Csv,
 }
 
 fn parse_args() -> Command {
@@ -44,105 +62,234 @@ fn parse_args() -> Command {
                 .num_args(1)
                 .action(ArgAction::Set),
         )
+        .arg(
+            Arg::new("max-sources")
+                .long("max-sources")
+                .help("Fail before fetching anything if given more sources than this")
+                .value_parser(clap::value_parser!(usize))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .help("Maximum number of sources fetched at the same time")
+                .value_parser(clap::value_parser!(NonZeroUsize))
+                .default_value("8")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .help("Seconds to wait for each request to a source URL")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .help("Times to retry a source URL after a timeout or 5xx response")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("deep")
+                .long("deep")
+                .help("Merge nested JSON objects recursively instead of replacing them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("array-strategy")
+                .long("array-strategy")
+                .help("How --deep merges arrays at the same key")
+                .value_parser(clap::value_parser!(ArrayStrategy))
+                .default_value("replace")
+                .requires("deep")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .help("Drop plaintext entries older than this duration (e.g. 1h, 30m)")
+                .value_parser(humantime::parse_duration)
+                .requires("timestamp")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("timestamp-field")
+                .long("timestamp-field")
+                .help("JSON field holding the timestamp of each JSONL entry, for --since")
+                .requires("since")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("timestamp-regex")
+                .long("timestamp-regex")
+                .help("Regex whose first capture group is the timestamp of each line, for --since")
+                .value_parser(Regex::new)
+                .requires("since")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("interpolate-env")
+                .long("interpolate-env")
+                .help("Replace ${VAR} and ${VAR:-default} in INI values with environment variables")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedup")
+                .long("dedup")
+                .help("Drop repeated plaintext lines, keeping blank lines and # comments")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Sort plaintext lines, leaving blank lines and # comments in place")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("csv-ignore-extra")
+                .long("csv-ignore-extra")
+                .help("Drop CSV columns missing from the first source's header instead of failing")
+                .action(ArgAction::SetTrue),
+        )
+        .group(
+            ArgGroup::new("timestamp")
+                .args(["timestamp-field", "timestamp-regex"])
+                .multiple(false),
+        )
 }
 
-async fn fetch_content(
-    client: &reqwest::Client,
-    source: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    if source.starts_with("http://") || source.starts_with("https://") {
-        tracing::info!("Fetching from URL: {}", source);
-        let response = client.get(source).send().await?;
-        if response.status().is_success() {
-            Ok(response.text().await?)
-        } else {
-            Err(format!("HTTP error {} from {}", response.status(), source).into())
-        }
-    } else {
-        tracing::info!("Reading from file: {}", source);
-        let path = Path::new(source);
-        if !path.exists() {
-            return Err(format!("File does not exist: {source}").into());
-        }
-        Ok(std::fs::read_to_string(path)?)
+/// Rejects `count` sources if it exceeds `--max-sources`, which usually means
+/// a glob expanded to far more files than intended.
+fn check_source_count(count: usize, max_sources: Option<usize>) -> Result<(), String> {
+    match max_sources {
+        Some(max_sources) if count > max_sources => Err(format!(
+            "Got {count} sources, more than --max-sources {max_sources}"
+        )),
+        _ => Ok(()),
     }
 }
 
-fn merge_json_contents(contents: Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
-    let mut merged_object = serde_json::Map::new();
+/// Where to find the timestamp of a line-oriented entry.
+enum TimestampSource {
+    /// The line is a JSON object with the timestamp in this field.
+    JsonField(String),
+    /// The first capture group (or the whole match) is the timestamp.
+    Regex(Regex),
+}
 
-    for content in contents {
-        let value: Value = serde_json::from_str(&content)?;
+/// Parses an RFC 3339 timestamp (UTC) or a number of seconds since the epoch.
+fn parse_timestamp(raw: &str) -> Option<SystemTime> {
+    if let Ok(secs) = raw.parse::<f64>() {
+        return Some(SystemTime::UNIX_EPOCH + Duration::try_from_secs_f64(secs).ok()?);
+    }
+    humantime::parse_rfc3339_weak(raw).ok()
+}
 
-        if let Value::Object(obj) = value {
-            for (key, val) in obj {
-                merged_object.insert(key, val);
+fn entry_timestamp(line: &str, source: &TimestampSource) -> Option<SystemTime> {
+    match source {
+        TimestampSource::JsonField(field) => {
+            let value: Value = serde_json::from_str(line).ok()?;
+            match value.get(field)? {
+                Value::String(raw) => parse_timestamp(raw),
+                Value::Number(secs) => parse_timestamp(&secs.to_string()),
+                _ => None,
             }
-        } else {
-            return Err("All JSON sources must be objects".into());
+        }
+        TimestampSource::Regex(regex) => {
+            let captures = regex.captures(line)?;
+            let raw = captures.get(1).or_else(|| captures.get(0))?;
+            parse_timestamp(raw.as_str())
         }
     }
-
-    Ok(serde_json::to_string_pretty(&Value::Object(merged_object))?)
 }
 
-fn merge_plaintext_contents(contents: Vec<String>) -> String {
-    contents.join("\n")
+/// Removes the lines of `content` whose timestamp is before `cutoff`. Lines
+/// without a recognizable timestamp are kept.
+fn filter_since(content: &str, cutoff: SystemTime, source: &TimestampSource) -> String {
+    content
+        .lines()
+        .filter(|line| entry_timestamp(line, source).is_none_or(|timestamp| timestamp >= cutoff))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-fn merge_ini_contents(contents: Vec<String>) -> Result<String, Box<dyn std::error::Error>> {
-    use indexmap::IndexMap;
+/// Wait before the first retry of a source, doubled for every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
 
-    let mut merged_map: IndexMap<String, IndexMap<String, Option<String>>> = IndexMap::new();
+/// Longest wait between two retries of a source.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
 
-    for content in contents {
-        // Parse INI content manually to preserve case
-        let mut current_section = String::new();
+/// Wait before retrying a source for the `attempt + 1`th time.
+fn retry_backoff(attempt: u32) -> Duration {
+    RETRY_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_BACKOFF)
+}
 
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+/// Reads a file or fetches a URL. Timeouts, connection errors and `5xx`
+/// responses are retried up to `retries` times; other failures are returned
+/// right away.
+async fn fetch_content(
+    client: &reqwest::Client,
+    source: &str,
+    retries: u32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        tracing::info!("Fetching from URL: {}", source);
+        let mut attempt = 0;
+        loop {
+            let result = client.get(source).send().await;
+            let transient = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+            if transient && attempt < retries {
+                let backoff = retry_backoff(attempt);
+                tracing::warn!("Fetching {} failed, retrying in {:?}", source, backoff);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
                 continue;
             }
 
-            if line.starts_with('[') && line.ends_with(']') {
-                // Section header
-                current_section = line[1..line.len() - 1].to_string();
-                merged_map.entry(current_section.clone()).or_default();
-            } else if let Some(eq_pos) = line.find('=') {
-                // Key-value pair
-                let key = line[..eq_pos].trim().to_string();
-                let value = line[eq_pos + 1..].trim().to_string();
-
-                let section_map = merged_map.entry(current_section.clone()).or_default();
-                section_map.insert(key, if value.is_empty() { None } else { Some(value) });
+            let response = result?;
+            return if response.status().is_success() {
+                Ok(response.text().await?)
             } else {
-                // Key without value
-                let key = line.to_string();
-                let section_map = merged_map.entry(current_section.clone()).or_default();
-                section_map.insert(key, None);
-            }
-        }
-    }
-
-    // Convert back to INI format
-    let mut output = String::new();
-
-    for (section_name, section) in merged_map {
-        if !section_name.is_empty() {
-            output.push_str(&format!("[{section_name}]\n"));
+                Err(format!("HTTP error {} from {}", response.status(), source).into())
+            };
         }
-
-        for (key, value) in section {
-            match value {
-                Some(val) => output.push_str(&format!("{key}={val}\n")),
-                None => output.push_str(&format!("{key}\n")),
-            }
+    } else {
+        tracing::info!("Reading from file: {}", source);
+        let path = Path::new(source);
+        if !path.exists() {
+            return Err(format!("File does not exist: {source}").into());
         }
-        output.push('\n');
+        Ok(std::fs::read_to_string(path)?)
     }
+}
 
-    Ok(output)
+/// Fetches `sources` with at most `concurrency` requests at a time. The
+/// contents keep the order of `sources`, whichever finishes first. On failure,
+/// the source that failed is returned with its error.
+async fn fetch_all<'a>(
+    client: &reqwest::Client,
+    sources: &[&'a String],
+    concurrency: usize,
+    retries: u32,
+) -> Result<Vec<String>, (&'a String, Box<dyn std::error::Error>)> {
+    futures_util::stream::iter(sources)
+        .map(|source| async move {
+            fetch_content(client, source, retries)
+                .await
+                .map_err(|e| (*source, e))
+        })
+        .buffered(concurrency)
+        .try_collect()
+        .await
 }
 
 #[tokio::main]
@@ -167,27 +314,91 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or_default()
         .collect();
 
+    if let Err(e) = check_source_count(
+        sources.len(),
+        matches.get_one::<usize>("max-sources").copied(),
+    ) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
     let merge_type = matches.get_one::<MergeType>("type").unwrap();
+
+    let interpolate_env = matches.get_flag("interpolate-env");
+    if interpolate_env && !matches!(merge_type, MergeType::Ini) {
+        eprintln!("--interpolate-env is only supported with --type ini");
+        std::process::exit(1);
+    }
+
+    let csv_ignore_extra = matches.get_flag("csv-ignore-extra");
+    if csv_ignore_extra && !matches!(merge_type, MergeType::Csv) {
+        eprintln!("--csv-ignore-extra is only supported with --type csv");
+        std::process::exit(1);
+    }
+
+    let dedup = matches.get_flag("dedup");
+    let sort = matches.get_flag("sort");
+    if (dedup || sort) && !matches!(merge_type, MergeType::Plaintext) {
+        eprintln!("--dedup and --sort are only supported with --type plaintext");
+        std::process::exit(1);
+    }
+
+    let deep = matches.get_flag("deep");
+    if deep && !matches!(merge_type, MergeType::Json) {
+        eprintln!("--deep is only supported with --type json");
+        std::process::exit(1);
+    }
+    let array_strategy = *matches.get_one::<ArrayStrategy>("array-strategy").unwrap();
+
+    if matches.get_one::<Duration>("since").is_some() && !matches!(merge_type, MergeType::Plaintext)
+    {
+        eprintln!("--since is only supported with --type plaintext");
+        std::process::exit(1);
+    }
+
     let output_file = matches.get_one::<String>("output");
 
-    let client = reqwest::Client::new();
-    let mut contents = Vec::new();
+    let concurrency = matches
+        .get_one::<NonZeroUsize>("concurrency")
+        .unwrap()
+        .get();
 
-    for source in sources {
-        match fetch_content(&client, source).await {
-            Ok(content) => contents.push(content),
-            Err(e) => {
-                tracing::error!("Failed to fetch from {source}: {e}");
-                eprintln!("Failed to fetch from {source}: {e}");
-                std::process::exit(1);
-            }
+    let timeout = Duration::from_secs(*matches.get_one::<u64>("timeout").unwrap());
+    let retries = *matches.get_one::<u32>("retries").unwrap();
+
+    let client = reqwest::Client::builder().timeout(timeout).build()?;
+    let mut contents = match fetch_all(&client, &sources, concurrency, retries).await {
+        Ok(contents) => contents,
+        Err((source, e)) => {
+            tracing::error!("Failed to fetch from {source}: {e}");
+            eprintln!("Failed to fetch from {source}: {e}");
+            std::process::exit(1);
         }
+    };
+
+    if let Some(since) = matches.get_one::<Duration>("since") {
+        let source = match matches.get_one::<String>("timestamp-field") {
+            Some(field) => TimestampSource::JsonField(field.clone()),
+            None => {
+                TimestampSource::Regex(matches.get_one::<Regex>("timestamp-regex").unwrap().clone())
+            }
+        };
+        let cutoff = SystemTime::now() - *since;
+        contents = contents
+            .iter()
+            .map(|content| filter_since(content, cutoff, &source))
+            .collect();
     }
 
     let merged_content = match merge_type {
-        MergeType::Json => merge_json_contents(contents)?,
-        MergeType::Plaintext => merge_plaintext_contents(contents),
-        MergeType::Ini => merge_ini_contents(contents)?,
+        MergeType::Json => merge_json_contents(contents, deep.then_some(array_strategy))?,
+        MergeType::Plaintext => {
+            dedup_and_sort_lines(&merge_plaintext_contents(contents), dedup, sort)
+        }
+        MergeType::Ini => merge_ini_contents(contents, interpolate_env)?,
+        MergeType::Toml => merge_toml_contents(contents)?,
+        MergeType::Yaml => merge_yaml_contents(contents)?,
+        MergeType::Csv => merge_csv_contents(contents, csv_ignore_extra)?,
     };
 
     if let Some(output_path) = output_file {
@@ -199,3 +410,220 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn at(timestamp: &str) -> SystemTime {
+        humantime::parse_rfc3339(timestamp).unwrap()
+    }
+
+    #[test]
+    fn test_filter_since_jsonl() {
+        let content = r#"{"ts":"2024-01-01T00:00:00Z","msg":"old"}
+{"ts":"2024-01-02T12:00:00Z","msg":"recent"}
+{"ts":1704240000,"msg":"epoch"}
+{"msg":"no timestamp"}"#;
+        let source = TimestampSource::JsonField("ts".to_string());
+
+        let filtered = filter_since(content, at("2024-01-02T00:00:00Z"), &source);
+        assert_eq!(
+            filtered,
+            r#"{"ts":"2024-01-02T12:00:00Z","msg":"recent"}
+{"ts":1704240000,"msg":"epoch"}
+{"msg":"no timestamp"}"#
+        );
+
+        let filtered = filter_since(content, at("2024-01-03T00:00:01Z"), &source);
+        assert_eq!(filtered, r#"{"msg":"no timestamp"}"#);
+    }
+
+    #[test]
+    fn test_filter_since_regex() {
+        let content = "[2024-01-01 00:00:00] old\n[2024-01-02 12:00:00] recent";
+        let source = TimestampSource::Regex(Regex::new(r"^\[([^\]]+)\]").unwrap());
+
+        let filtered = filter_since(content, at("2024-01-02T00:00:00Z"), &source);
+        assert_eq!(filtered, "[2024-01-02 12:00:00] recent");
+    }
+
+    /// Serves `GET /<delay_ms>/<name>`, responding `name` after `delay_ms`.
+    async fn spawn_delayed_server() -> String {
+        use tokio::io::AsyncReadExt;
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 1024];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let path = request.split_whitespace().nth(1).unwrap();
+                    let (delay_ms, name) = path[1..].split_once('/').unwrap();
+                    tokio::time::sleep(Duration::from_millis(delay_ms.parse().unwrap())).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{name}",
+                        name.len()
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_preserves_order() {
+        let base = spawn_delayed_server().await;
+        let sources = [
+            format!("{base}/300/first"),
+            format!("{base}/0/second"),
+            format!("{base}/100/third"),
+        ];
+        let sources: Vec<&String> = sources.iter().collect();
+
+        let client = reqwest::Client::new();
+        for concurrency in [1, 3] {
+            let contents = fetch_all(&client, &sources, concurrency, 0).await.unwrap();
+            assert_eq!(contents, ["first", "second", "third"]);
+        }
+
+        let missing = "/nonexistent/junction-merger-source".to_string();
+        let sources = vec![sources[0], &missing];
+        let (failed, _) = fetch_all(&client, &sources, 2, 0).await.unwrap_err();
+        assert_eq!(failed, &missing);
+    }
+
+    /// Serves `GET /<status>`, responding with that status and counting the
+    /// requests, then `200 OK` once `failures` requests have been answered.
+    async fn spawn_flaky_server(failures: usize) -> (String, Arc<AtomicUsize>) {
+        use tokio::io::AsyncReadExt;
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let status = &request.split_whitespace().nth(1).unwrap()[1..];
+                let status = if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    status
+                } else {
+                    "200"
+                };
+                let response = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (format!("http://{addr}"), requests)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_content_retries_server_errors() {
+        let client = reqwest::Client::new();
+
+        let (base, requests) = spawn_flaky_server(2).await;
+        let content = fetch_content(&client, &format!("{base}/503"), 2)
+            .await
+            .unwrap();
+        assert_eq!(content, "ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let (base, requests) = spawn_flaky_server(2).await;
+        let err = fetch_content(&client, &format!("{base}/503"), 1)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("HTTP error 503"));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_content_client_errors_not_retried() {
+        let client = reqwest::Client::new();
+
+        let (base, requests) = spawn_flaky_server(1).await;
+        let err = fetch_content(&client, &format!("{base}/404"), 3)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("HTTP error 404"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(0), Duration::from_millis(100));
+        assert_eq!(retry_backoff(3), Duration::from_millis(800));
+        assert_eq!(retry_backoff(20), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(u32::MAX), MAX_RETRY_BACKOFF);
+    }
+
+    #[test]
+    fn test_max_sources() {
+        let matches = parse_args()
+            .try_get_matches_from([
+                "junction-merger",
+                "--type",
+                "plaintext",
+                "--max-sources",
+                "2",
+                "a.txt",
+                "b.txt",
+                "c.txt",
+            ])
+            .unwrap();
+        let sources = matches.get_many::<String>("sources").unwrap().count();
+        let max_sources = matches.get_one::<usize>("max-sources").copied();
+        assert_eq!(
+            check_source_count(sources, max_sources).unwrap_err(),
+            "Got 3 sources, more than --max-sources 2"
+        );
+
+        assert!(check_source_count(2, Some(2)).is_ok());
+        assert!(check_source_count(10_000, None).is_ok());
+    }
+
+    #[test]
+    fn test_since_requires_timestamp_source() {
+        let result = parse_args().try_get_matches_from([
+            "junction-merger",
+            "--type",
+            "plaintext",
+            "--since",
+            "1h",
+            "a.log",
+        ]);
+        assert!(result.is_err());
+
+        let matches = parse_args()
+            .try_get_matches_from([
+                "junction-merger",
+                "--type",
+                "plaintext",
+                "--since",
+                "1h",
+                "--timestamp-field",
+                "ts",
+                "a.log",
+            ])
+            .unwrap();
+        assert_eq!(
+            matches.get_one::<Duration>("since"),
+            Some(&Duration::from_secs(3600))
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use std::process::Command;
+use std::time::SystemTime;
+
+fn main() {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=JUNCTION_BUILD_TIMESTAMP={timestamp}");
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=JUNCTION_RUSTC_VERSION={rustc_version}");
+
+    // No `rerun-if-changed`, so Cargo reruns this whenever a file of the
+    // package changes and the timestamp follows the sources
+}
@@ -0,0 +1,115 @@
+use poem::http::header;
+use poem::http::StatusCode;
+use poem::Endpoint;
+use poem::Middleware;
+use poem::Request;
+use poem::Response;
+use poem::Result;
+use subtle::ConstantTimeEq;
+
+/// Middleware requiring an `Authorization: Bearer <key>` header on every
+/// request. Without a key, requests pass through unchanged.
+pub struct ApiKeyAuth {
+    api_key: Option<String>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(api_key: Option<String>) -> Self {
+        Self { api_key }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for ApiKeyAuth {
+    type Output = ApiKeyAuthEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ApiKeyAuthEndpoint {
+            inner: ep,
+            api_key: self.api_key.clone(),
+        }
+    }
+}
+
+pub struct ApiKeyAuthEndpoint<E> {
+    inner: E,
+    api_key: Option<String>,
+}
+
+impl<E: Endpoint> Endpoint for ApiKeyAuthEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        if let Some(api_key) = &self.api_key {
+            let provided = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            // Compare in constant time so the key can't be guessed byte by byte
+            let authorized = provided
+                .is_some_and(|provided| provided.as_bytes().ct_eq(api_key.as_bytes()).into());
+            if !authorized {
+                return Err(poem::Error::from_response(
+                    Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .header(header::WWW_AUTHENTICATE, "Bearer")
+                        .finish(),
+                ));
+            }
+        }
+
+        self.inner.call(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::test::TestClient;
+    use poem::EndpointExt;
+
+    use super::*;
+
+    fn protected(api_key: Option<&str>) -> impl Endpoint {
+        poem::endpoint::make_sync(|_| "secret").with(ApiKeyAuth::new(api_key.map(str::to_string)))
+    }
+
+    #[tokio::test]
+    async fn test_missing_header() {
+        let client = TestClient::new(protected(Some("s3cret")));
+        let resp = client.get("/").send().await;
+        resp.assert_status(StatusCode::UNAUTHORIZED);
+        resp.assert_header(header::WWW_AUTHENTICATE, "Bearer");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_key() {
+        let client = TestClient::new(protected(Some("s3cret")));
+        for value in ["Bearer wrong", "Bearer s3cre", "Basic s3cret", "s3cret"] {
+            let resp = client
+                .get("/")
+                .header(header::AUTHORIZATION, value)
+                .send()
+                .await;
+            resp.assert_status(StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_correct_key() {
+        let client = TestClient::new(protected(Some("s3cret")));
+        let resp = client
+            .get("/")
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_text("secret").await;
+    }
+
+    #[tokio::test]
+    async fn test_no_key_configured() {
+        let client = TestClient::new(protected(None));
+        let resp = client.get("/").send().await;
+        resp.assert_status_is_ok();
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -12,72 +13,959 @@ pub enum RawConfigError {
     IoError(#[from] std::io::Error),
     #[error("Failed to parse YAML config: {0}")]
     ParseError(#[from] serde_yaml::Error),
+    #[error("Failed to parse TOML config: {0}")]
+    TomlParseError(#[from] toml::de::Error),
+    #[error("Failed to parse JSON config: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+    #[error("Config file is {0} bytes, more than the maximum of {1} bytes")]
+    TooLarge(u64, u64),
 }
 
 #[derive(Debug, Error)]
 pub enum ResolvedConfigError {
     #[error("Duplicate public key found: {0}")]
     DuplicatePublicKey(String),
+    #[error("Invalid slug {0:?}: only letters, digits, `-` and `_` are allowed")]
+    InvalidSlug(String),
+    #[error("Output {0} must set exactly one of `cmd`, `script`, `shell` or `proxy_url`")]
+    InvalidCommand(String),
+    #[error("Output {0} has a working_dir outside of data_dir: {1}")]
+    WorkingDirOutsideDataDir(String, PathBuf),
+    #[error("Script not found: {0}")]
+    ScriptNotFound(PathBuf),
+    #[error("Script is not executable: {0}")]
+    ScriptNotExecutable(PathBuf),
+    #[error("Output {0} sets `enabled_flag`, which needs `flags_url` and the `flags` feature")]
+    FlagsUnavailable(String),
+    #[error("Output {0} cannot pass stdin to a command running in a pty")]
+    PtyWithStdin(String),
+    #[error("Output {0} cannot stream a command running in a pty")]
+    PtyWithStream(String),
+    #[error("Output {0} uses unknown base {1}")]
+    MissingBase(String, String),
+    #[error("Base {0} inherits from itself")]
+    BaseCycle(String),
+    #[error("Output {0} has {1} arguments, more than the maximum of {2}")]
+    TooManyArgs(String, usize, usize),
+    #[error("Output {0} has an argument longer than the maximum of {1} bytes")]
+    ArgTooLong(String, usize),
+    #[error("Output {0} has an invalid content_type: {1}")]
+    InvalidContentType(String, String),
+    #[error("Output {0} has a log_sample_rate outside of 0.0 to 1.0")]
+    InvalidLogSampleRate(String),
+    #[error("Output {0} has an invalid path parameter name {1:?}")]
+    InvalidPathParam(String, String),
+    #[error("Output {0} uses environment variable {1}, which is not defined")]
+    UndefinedEnvVar(String, String),
+}
+
+#[derive(Debug, Error)]
+pub enum LoadConfigError {
+    #[error(transparent)]
+    Raw(#[from] RawConfigError),
+    #[error(transparent)]
+    Resolved(#[from] ResolvedConfigError),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub outputs: Vec<OutputConfig>,
+    /// Named templates outputs can inherit their command from with `base`.
+    #[serde(default)]
+    pub bases: HashMap<String, OutputBase>,
+    /// Maximum number of connections open at the same time. Further
+    /// connections wait until a slot frees up.
+    pub max_connections: Option<usize>,
+    /// Maximum number of `POST /output/<slug>` requests whose bodies are
+    /// buffered at the same time. Further uploads get `503 Service
+    /// Unavailable`.
+    pub max_uploads: Option<usize>,
+    /// Maximum number of commands running at the same time. Further
+    /// commands wait for a slot, unless their output sets
+    /// `reject_when_busy`. Only read at startup.
+    pub max_concurrent_commands: Option<usize>,
+    /// Respond `503 Service Unavailable` to requests that waited this many
+    /// seconds for a `max_concurrent_commands` slot.
+    pub queue_timeout_secs: Option<u64>,
+    /// Respond `504 Gateway Timeout` to output requests still running after
+    /// this many seconds, killing their commands.
+    pub max_request_time_secs: Option<u64>,
+    /// Wait until no SIGHUP arrived for this many milliseconds before
+    /// reloading, so a burst of signals, e.g. one per saved file, reloads the
+    /// config once.
+    pub reload_debounce_ms: Option<u64>,
+    /// Check that every output's script exists and is executable when the
+    /// config is resolved, instead of failing on the first request.
+    #[serde(default)]
+    pub verify_commands_on_start: bool,
+    /// Prepend the executable directory and `data_dir` to the `PATH` of
+    /// commands. When disabled, commands inherit `PATH` unchanged.
+    #[serde(default = "default_true")]
+    pub modify_path: bool,
+    /// `PATH` commands run with, used verbatim instead of the inherited one.
+    /// Takes precedence over `modify_path`.
+    pub command_path: Option<String>,
+    /// Environment variables of the server that commands don't inherit, e.g.
+    /// secrets. Outputs may still set them with `env`.
+    #[serde(default)]
+    pub strip_env: Vec<String>,
+    /// Base URL of the feature-flag service consulted for `enabled_flag`.
+    pub flags_url: Option<String>,
+    /// How long a flag value is cached, in seconds. Defaults to 5.
+    pub flags_cache_secs: Option<u64>,
+    /// Directory commands run in, relative to `data_dir`. Defaults to
+    /// `data_dir` itself.
+    pub work_dir: Option<PathBuf>,
+    /// Maximum number of arguments a command of any output may have.
+    pub max_args: Option<usize>,
+    /// Maximum length in bytes of each command argument.
+    pub max_arg_length: Option<usize>,
+    /// Query parameters passed to every output with `allow_query_env`, in
+    /// addition to the output's own `allowed_query_params`.
+    #[serde(default)]
+    pub allowed_query_params: Vec<String>,
+    /// When set, every request must send `Authorization: Bearer <api_key>`,
+    /// and `POST /reload` and `POST /cache/invalidate` are enabled. Only read
+    /// at startup.
+    pub api_key: Option<String>,
+    /// Template file rendered as the body of failed commands instead of their
+    /// raw stderr. Supports `{{ slug }}`, `{{ exit_code }}` and `{{ stderr }}`.
+    pub error_template: Option<PathBuf>,
+    /// Serve `GET /help`, a plaintext summary of the endpoints and outputs.
+    #[serde(default)]
+    pub enable_help: bool,
+    /// Enable request options meant for debugging, such as overriding the
+    /// response type with `?content_type=<type>`, and report the directory
+    /// commands ran in with `X-Working-Dir`.
+    #[serde(default)]
+    pub allow_debug: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ResolvedConfig {
     pub outputs: HashMap<String, OutputConfig>,
     pub data_dir: PathBuf,
+    pub work_dir: Option<PathBuf>,
+    pub max_connections: Option<usize>,
+    pub max_uploads: Option<usize>,
+    pub max_concurrent_commands: Option<usize>,
+    pub queue_timeout_secs: Option<u64>,
+    pub max_request_time_secs: Option<u64>,
+    pub reload_debounce_ms: Option<u64>,
+    pub modify_path: bool,
+    pub command_path: Option<String>,
+    pub strip_env: Vec<String>,
+    pub flags_url: Option<String>,
+    pub flags_cache_secs: Option<u64>,
+    pub error_template: Option<PathBuf>,
+    pub enable_help: bool,
+    pub allow_debug: bool,
+    /// Never served by `/config`.
+    #[serde(skip)]
+    pub api_key: Option<String>,
+    /// The file the config was loaded from, re-read by `POST /reload`.
+    #[serde(skip)]
+    pub config_file: Option<ConfigFile>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Location and size limit of the config file.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub max_size: u64,
+}
+
+impl ConfigFile {
+    /// Reads and resolves the config file, remembering it in the result.
+    pub fn load(&self, data_dir: PathBuf) -> Result<ResolvedConfig, LoadConfigError> {
+        let config = Config::from_file(&self.path, self.max_size)?;
+        let mut resolved = ResolvedConfig::new(config, data_dir)?;
+        resolved.config_file = Some(self.clone());
+        Ok(resolved)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            outputs: Vec::new(),
+            bases: HashMap::new(),
+            max_connections: None,
+            max_uploads: None,
+            max_concurrent_commands: None,
+            queue_timeout_secs: None,
+            max_request_time_secs: None,
+            reload_debounce_ms: None,
+            verify_commands_on_start: false,
+            modify_path: true,
+            command_path: None,
+            strip_env: Vec::new(),
+            flags_url: None,
+            flags_cache_secs: None,
+            work_dir: None,
+            max_args: None,
+            max_arg_length: None,
+            allowed_query_params: Vec::new(),
+            api_key: None,
+            error_template: None,
+            enable_help: false,
+            allow_debug: false,
+        }
+    }
+}
+
+impl Default for ResolvedConfig {
+    fn default() -> Self {
+        Self {
+            outputs: HashMap::new(),
+            data_dir: PathBuf::new(),
+            work_dir: None,
+            max_connections: None,
+            max_uploads: None,
+            max_concurrent_commands: None,
+            queue_timeout_secs: None,
+            max_request_time_secs: None,
+            reload_debounce_ms: None,
+            modify_path: true,
+            command_path: None,
+            strip_env: Vec::new(),
+            flags_url: None,
+            flags_cache_secs: None,
+            error_template: None,
+            enable_help: false,
+            allow_debug: false,
+            api_key: None,
+            config_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct OutputConfig {
     pub slug: String,
+    /// What the output shows, listed by `/help`.
+    pub description: Option<String>,
+    /// Name of an entry in `bases` to inherit `cmd`, `args` and `env` from.
+    /// Fields set on the output itself take precedence, and so do its own
+    /// `env` variables.
+    pub base: Option<String>,
+    pub cmd: Option<String>,
+    /// Script to execute directly instead of `cmd`, relative to `data_dir`.
+    /// The script's shebang decides which interpreter runs it.
+    pub script: Option<PathBuf>,
+    /// Command line run with `shell_binary -c`, for pipelines such as
+    /// `curl -s ... | jq .items`. `args` are passed as `$1`, `$2`, ... and
+    /// the slug as `$0`.
+    ///
+    /// The shell interprets the whole string, so it must never be built from
+    /// untrusted input. Pass request values through `args` or environment
+    /// variables instead, and quote them, e.g. `"$JUNCTION_ARG_name"`.
+    pub shell: Option<String>,
+    /// Shell that runs `shell`. Defaults to `/bin/sh`.
+    pub shell_binary: Option<String>,
+    /// Arguments of the command. Unless set, even to `[]`, they are
+    /// inherited from `base`.
+    pub args: Option<Vec<String>>,
+    /// Names of path segments after the slug, in order, whose values replace
+    /// `{name}` in `args`. With `path_params: [id]` and `args: ["{id}"]`,
+    /// the output is served at `/output/<slug>/<id>` instead.
+    #[serde(default)]
+    pub path_params: Vec<String>,
+    /// Expand the output into one output per value, replacing `{{ value }}`
+    /// in its slug, description and arguments.
+    #[serde(default)]
+    pub matrix: Vec<String>,
+    /// Directory the commands of this output run in, relative to `data_dir`.
+    /// Absolute paths must also be inside `data_dir`. Overrides `work_dir`.
+    pub working_dir: Option<PathBuf>,
+    /// Create `working_dir` before running a command if it doesn't exist.
+    #[serde(default)]
+    pub create_working_dir: bool,
+    /// Environment variables set for the commands, on top of the inherited
+    /// environment and `PATH`. `${VAR}` is replaced with the server's own
+    /// `VAR` when the config is resolved. Not served by `/config`, since
+    /// values may be secrets.
+    #[serde(default, skip_serializing)]
+    pub env: HashMap<String, String>,
+    /// Commands tried in order when the primary command fails. The first one
+    /// to succeed provides the output.
+    #[serde(default)]
+    pub fallbacks: Vec<OutputCommand>,
+    /// Minimum number of seconds between two runs of the command. Requests
+    /// arriving sooner are rejected with `429 Too Many Requests`.
+    pub min_interval_secs: Option<u64>,
+    /// How to respond when the command succeeds without writing to stdout.
+    #[serde(default)]
+    pub empty_output: EmptyOutput,
+    /// Feature flag that must be on for the output to be served; otherwise it
+    /// responds `404 Not Found`. Requires the `flags` feature.
+    pub enabled_flag: Option<String>,
+    /// Run the command attached to a pseudo-terminal (Unix only), for
+    /// commands that only emit colors or line-buffer when they see a TTY.
+    /// Stdout and stderr are combined.
+    #[serde(default)]
+    pub pty: bool,
+    /// Testing aid: wait this many milliseconds before responding, e.g. to
+    /// exercise client timeouts.
+    pub artificial_delay_ms: Option<u64>,
+    /// Stream stdout to the client as the command produces it instead of
+    /// buffering it. Streamed outputs are not cached, minified, paginated or
+    /// retried with fallbacks, and a failure after streaming started keeps
+    /// the `200` status.
+    #[serde(default)]
+    pub stream: bool,
+    /// How a streamed output tells the client that its command failed or
+    /// was killed after the response started.
+    #[serde(default)]
+    pub stream_error_mode: StreamErrorMode,
+    /// Serve the output of a successful run for this many seconds before
+    /// running the command again.
+    pub cache_ttl_secs: Option<u64>,
+    /// Files, relative to `data_dir`, the output is generated from. The
+    /// output is cached until one of their modification times changes, or
+    /// until `cache_ttl_secs` passes if that is also set.
+    #[serde(default)]
+    pub cache_inputs: Vec<PathBuf>,
+    /// Once the cached output has expired, keep serving it with `202
+    /// Accepted` and `X-Cache: stale` while the command runs again in the
+    /// background, instead of making the request wait.
+    #[serde(default)]
+    pub async_refresh: bool,
+    /// Probability, from 0.0 to 1.0, that the stderr of a successful run is
+    /// logged. Failures are always logged.
+    pub log_sample_rate: Option<f64>,
+    /// Kill the command and respond `504 Gateway Timeout` if it runs longer
+    /// than this many seconds. Streamed outputs have already responded, so
+    /// the interruption is signalled per `stream_error_mode` instead.
+    pub timeout_secs: Option<u64>,
+    /// `Content-Type` of the response. Defaults to
+    /// `text/plain; charset=utf-8`.
+    pub content_type: Option<String>,
+    /// Post-process the output to save bandwidth. Output that isn't valid
+    /// for the chosen format responds `502 Bad Gateway`.
+    #[serde(default)]
+    pub minify: Minify,
+    /// Normalize line endings of the output before serving it.
+    #[serde(default)]
+    pub line_endings: LineEndings,
+    /// Serve the response of this URL, e.g. another junction's
+    /// `/output/<slug>`, instead of running a command. The upstream status
+    /// and `Content-Type` are kept.
+    pub proxy_url: Option<String>,
+    /// URL the output of every successful run is POSTed to in the
+    /// background, with the slug in `X-Junction-Slug`. Failures are only
+    /// logged.
+    pub webhook_url: Option<String>,
+    /// URL fetched on every request and piped to the command's stdin.
+    pub stdin_url: Option<String>,
+    /// Accept `POST /output/<slug>` and pipe the request body to the
+    /// command's stdin. Other outputs answer `405 Method Not Allowed`.
+    #[serde(default)]
+    pub accept_stdin: bool,
+    /// Respond `413 Payload Too Large` to `accept_stdin` request bodies
+    /// larger than this many bytes.
+    pub max_stdin_bytes: Option<usize>,
+    /// Respond `503 Service Unavailable` instead of waiting when
+    /// `max_concurrent_commands` commands are already running.
+    #[serde(default)]
+    pub reject_when_busy: bool,
+    /// Set `JUNCTION_REQUEST_METHOD` and `JUNCTION_REQUEST_PATH` for the
+    /// command, so one script can serve several routes.
+    #[serde(default)]
+    pub pass_request_meta: bool,
+    /// Pass each query parameter to the command as a `JUNCTION_ARG_<name>`
+    /// environment variable. Names may only contain `[A-Za-z0-9_]`.
+    /// `offset`, `limit` and `content_type` are read by the server instead.
+    #[serde(default)]
+    pub allow_query_env: bool,
+    /// Query parameters passed with `allow_query_env`; others are ignored.
+    /// When neither this nor the global list is set, every parameter is
+    /// passed.
+    #[serde(default)]
+    pub allowed_query_params: Vec<String>,
+}
+
+/// Response for a command that succeeds without producing any output.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "EmptyOutputRepr", into = "String")]
+pub enum EmptyOutput {
+    /// `200 OK` with an empty body.
+    #[default]
+    Ok,
+    /// `204 No Content`.
+    NoContent,
+    /// `502 Bad Gateway` with an error message.
+    Error,
+}
+
+/// Accepts `empty_output: 204` as a number as well as a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EmptyOutputRepr {
+    Code(u16),
+    Name(String),
+}
+
+impl TryFrom<EmptyOutputRepr> for EmptyOutput {
+    type Error = String;
+
+    fn try_from(repr: EmptyOutputRepr) -> Result<Self, String> {
+        match repr {
+            EmptyOutputRepr::Code(204) => Ok(EmptyOutput::NoContent),
+            EmptyOutputRepr::Name(name) if name == "204" => Ok(EmptyOutput::NoContent),
+            EmptyOutputRepr::Name(name) if name == "ok" => Ok(EmptyOutput::Ok),
+            EmptyOutputRepr::Name(name) if name == "error" => Ok(EmptyOutput::Error),
+            EmptyOutputRepr::Code(code) => Err(format!("invalid empty_output: {code}")),
+            EmptyOutputRepr::Name(name) => Err(format!("invalid empty_output: {name}")),
+        }
+    }
+}
+
+impl From<EmptyOutput> for String {
+    fn from(empty_output: EmptyOutput) -> Self {
+        match empty_output {
+            EmptyOutput::Ok => "ok",
+            EmptyOutput::NoContent => "204",
+            EmptyOutput::Error => "error",
+        }
+        .to_string()
+    }
+}
+
+/// How to minify the output of a command.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Minify {
+    /// Serve the output unchanged.
+    #[default]
+    None,
+    /// Re-serialize JSON without whitespace.
+    Json,
+    /// Minify HTML, including inline CSS and JavaScript.
+    Html,
+}
+
+/// Line endings to serve the output of a command with.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    /// Serve the output unchanged.
+    #[default]
+    Preserve,
+    /// Replace `\r\n` with `\n`.
+    Lf,
+    /// Replace lone `\n` with `\r\n`.
+    Crlf,
+}
+
+/// How a streamed output signals a command that failed mid-stream.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamErrorMode {
+    /// End the body normally and send the exit code in an `X-Exit-Code`
+    /// trailer, which is left out if the command was killed.
+    #[default]
+    Trailer,
+    /// End the body with a server-sent `event: error` carrying the reason,
+    /// for `text/event-stream` clients that can't read trailers.
+    Event,
+}
+
+/// Shared command settings that outputs inherit with `base`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OutputBase {
+    /// Another base to inherit from.
+    pub base: Option<String>,
+    pub cmd: Option<String>,
+    pub args: Option<Vec<String>>,
+    /// Merged into the `env` of inheriting outputs, whose own variables win.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct OutputCommand {
     pub cmd: String,
+    #[serde(default)]
     pub args: Vec<String>,
 }
 
 impl OutputConfig {
     pub fn get_command_parts(&self) -> (String, Vec<String>) {
-        (self.cmd.clone(), self.args.clone())
+        if let Some(shell) = &self.shell {
+            let binary = self.shell_binary.as_deref().unwrap_or(DEFAULT_SHELL);
+            let args = ["-c".to_string(), shell.clone(), self.slug.clone()]
+                .into_iter()
+                .chain(self.args.iter().flatten().cloned())
+                .collect();
+            return (binary.to_string(), args);
+        }
+        let program = match &self.script {
+            Some(script) => script.to_string_lossy().to_string(),
+            None => self.cmd.clone().unwrap_or_default(),
+        };
+        (program, self.args.clone().unwrap_or_default())
+    }
+
+    /// Replaces the `{name}` placeholders in `args` and the fallbacks'
+    /// arguments with the given values.
+    pub fn substitute_path_params(&mut self, params: &[(String, String)]) {
+        let substitute = |arg: &String| {
+            params.iter().fold(arg.clone(), |arg, (name, value)| {
+                arg.replace(&format!("{{{name}}}"), value)
+            })
+        };
+        if let Some(args) = &mut self.args {
+            *args = args.iter().map(substitute).collect();
+        }
+        for fallback in &mut self.fallbacks {
+            fallback.args = fallback.args.iter().map(substitute).collect();
+        }
     }
 }
 
+/// Commented starter config written by `junction init`.
+pub const SAMPLE_CONFIG: &str = r#"# Junction config
+#
+# Every output is served at /output/<slug> and runs its command in the data
+# directory, responding with whatever the command writes to stdout.
+outputs:
+  - slug: "hello"
+    # Program to run, looked up in PATH (the data directory is added to it)
+    cmd: "echo"
+    # Arguments passed to the program, without any shell interpretation
+    args:
+      - "Hello from Junction!"
+"#;
+
+/// Writes [`SAMPLE_CONFIG`] to `config.yaml` in `data_dir`, creating the
+/// directory if needed. An existing config is only replaced when `force` is set.
+pub fn write_sample_config(data_dir: &Path, force: bool) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(data_dir)?;
+
+    let path = data_dir.join("config.yaml");
+    if path.exists() && !force {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+        ));
+    }
+
+    std::fs::write(&path, SAMPLE_CONFIG)?;
+    Ok(path)
+}
+
+/// Default limit on the size of the config file, well above any real config.
+pub const DEFAULT_MAX_CONFIG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Shell that runs an output's `shell` unless it sets `shell_binary`.
+const DEFAULT_SHELL: &str = "/bin/sh";
+
 impl Config {
-    pub fn from_yaml_file(path: impl AsRef<Path>) -> Result<Self, RawConfigError> {
-        let file = std::fs::File::open(path)?;
-        let config = serde_yaml::from_reader(file)?;
-        Ok(config)
+    /// Loads the config from `path`, refusing files larger than `max_size`
+    /// bytes before parsing them. The file is read as TOML or JSON if its
+    /// extension is `.toml` or `.json`, and as YAML otherwise.
+    pub fn from_file(path: impl AsRef<Path>, max_size: u64) -> Result<Self, RawConfigError> {
+        let path = path.as_ref();
+        let content = read_config_file(path, max_size)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml_str(&content),
+            Some("json") => Self::from_json_str(&content),
+            _ => Self::from_yaml_str(&content),
+        }
+    }
+    pub fn from_yaml_file(path: impl AsRef<Path>, max_size: u64) -> Result<Self, RawConfigError> {
+        Self::from_yaml_str(&read_config_file(path.as_ref(), max_size)?)
     }
     pub fn from_yaml_str(yaml: &str) -> Result<Self, RawConfigError> {
-        let config = serde_yaml::from_str(yaml)?;
-        Ok(config)
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+    pub fn from_toml_str(toml: &str) -> Result<Self, RawConfigError> {
+        Ok(toml::from_str(toml)?)
+    }
+    pub fn from_json_str(json: &str) -> Result<Self, RawConfigError> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Reads a config file, refusing files larger than `max_size` bytes.
+fn read_config_file(path: &Path, max_size: u64) -> Result<String, RawConfigError> {
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    if size > max_size {
+        return Err(RawConfigError::TooLarge(size, max_size));
     }
+    Ok(std::io::read_to_string(file)?)
 }
 
 impl ResolvedConfig {
     pub fn get_output_by_slug(&self, slug: &str) -> Option<&OutputConfig> {
         self.outputs.get(slug)
     }
+
+    /// Whether commands run with the same directory and environment under
+    /// both configs, so their outputs are interchangeable.
+    pub fn same_command_settings(&self, other: &ResolvedConfig) -> bool {
+        self.data_dir == other.data_dir
+            && self.work_dir == other.work_dir
+            && self.modify_path == other.modify_path
+            && self.command_path == other.command_path
+            && self.strip_env == other.strip_env
+    }
+
+    /// The directory the commands of `output` run in.
+    pub fn command_dir<'a>(&'a self, output: &'a OutputConfig) -> &'a Path {
+        output
+            .working_dir
+            .as_deref()
+            .or(self.work_dir.as_deref())
+            .unwrap_or(&self.data_dir)
+    }
 }
 
 impl ResolvedConfig {
     pub fn new(config: Config, data_dir: PathBuf) -> Result<Self, ResolvedConfigError> {
         let mut outputs = HashMap::new();
 
-        for output in config.outputs {
+        let mut expanded = Vec::new();
+        for mut output in config.outputs {
+            apply_bases(&mut output, &config.bases)?;
+            expanded.extend(expand_matrix(output));
+        }
+
+        for mut output in expanded {
+            if !is_valid_slug(&output.slug) {
+                return Err(ResolvedConfigError::InvalidSlug(output.slug));
+            }
             if outputs.contains_key(&output.slug) {
                 return Err(ResolvedConfigError::DuplicatePublicKey(output.slug));
             }
 
+            let sources = [
+                output.cmd.is_some(),
+                output.script.is_some(),
+                output.shell.is_some(),
+                output.proxy_url.is_some(),
+            ];
+            if sources.iter().filter(|set| **set).count() != 1 {
+                return Err(ResolvedConfigError::InvalidCommand(output.slug));
+            }
+
+            check_arg_limits(&output, config.max_args, config.max_arg_length)?;
+
+            if let Some(content_type) = &output.content_type {
+                if !is_valid_mime(content_type) {
+                    return Err(ResolvedConfigError::InvalidContentType(
+                        output.slug,
+                        content_type.clone(),
+                    ));
+                }
+            }
+
+            if output
+                .log_sample_rate
+                .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+            {
+                return Err(ResolvedConfigError::InvalidLogSampleRate(output.slug));
+            }
+
+            if let Some(name) = output.path_params.iter().find(|name| !is_valid_slug(name)) {
+                return Err(ResolvedConfigError::InvalidPathParam(
+                    output.slug.clone(),
+                    name.clone(),
+                ));
+            }
+
+            if output.pty && (output.stdin_url.is_some() || output.accept_stdin) {
+                return Err(ResolvedConfigError::PtyWithStdin(output.slug));
+            }
+
+            if output.pty && output.stream {
+                return Err(ResolvedConfigError::PtyWithStream(output.slug));
+            }
+
+            if output.enabled_flag.is_some()
+                && (!cfg!(feature = "flags") || config.flags_url.is_none())
+            {
+                return Err(ResolvedConfigError::FlagsUnavailable(output.slug));
+            }
+
+            for value in output.env.values_mut() {
+                *value =
+                    interpolate_env(value, |name| std::env::var(name).ok()).map_err(|name| {
+                        ResolvedConfigError::UndefinedEnvVar(output.slug.clone(), name)
+                    })?;
+            }
+
+            for param in &config.allowed_query_params {
+                if !output.allowed_query_params.contains(param) {
+                    output.allowed_query_params.push(param.clone());
+                }
+            }
+
+            if let Some(working_dir) = &output.working_dir {
+                let resolved = std::path::absolute(data_dir.join(working_dir));
+                let root = std::path::absolute(&data_dir);
+                match (resolved, root) {
+                    (Ok(resolved), Ok(root))
+                        if normalize_path(&resolved).starts_with(normalize_path(&root)) =>
+                    {
+                        output.working_dir = Some(normalize_path(&resolved));
+                    }
+                    _ => {
+                        return Err(ResolvedConfigError::WorkingDirOutsideDataDir(
+                            output.slug,
+                            working_dir.clone(),
+                        ));
+                    }
+                }
+            }
+
+            if let Some(script) = &output.script {
+                let script = std::path::absolute(data_dir.join(script))
+                    .map_err(|_| ResolvedConfigError::ScriptNotFound(script.clone()))?;
+                if config.verify_commands_on_start {
+                    verify_script(&script)?;
+                }
+                output.script = Some(script);
+            }
+
             outputs.insert(output.slug.clone(), output);
         }
 
-        Ok(ResolvedConfig { outputs, data_dir })
+        let error_template = config
+            .error_template
+            .map(|template| data_dir.join(template));
+
+        let work_dir = config.work_dir.map(|work_dir| data_dir.join(work_dir));
+
+        Ok(ResolvedConfig {
+            outputs,
+            data_dir,
+            work_dir,
+            max_connections: config.max_connections,
+            max_uploads: config.max_uploads,
+            max_concurrent_commands: config.max_concurrent_commands,
+            queue_timeout_secs: config.queue_timeout_secs,
+            max_request_time_secs: config.max_request_time_secs,
+            reload_debounce_ms: config.reload_debounce_ms,
+            modify_path: config.modify_path,
+            command_path: config.command_path,
+            strip_env: config.strip_env,
+            flags_url: config.flags_url,
+            flags_cache_secs: config.flags_cache_secs,
+            error_template,
+            enable_help: config.enable_help,
+            allow_debug: config.allow_debug,
+            api_key: config.api_key,
+            config_file: None,
+        })
     }
 }
 
+/// Replaces every `${VAR}` in `value` with `lookup(VAR)`, or returns the name
+/// of the first variable that is not defined.
+fn interpolate_env(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        output.push_str(&rest[..start]);
+        output.push_str(&lookup(name).ok_or_else(|| name.to_string())?);
+        rest = &rest[start + 2 + len + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Resolves `.` and `..` in `path` without touching the filesystem, so that
+/// paths can be compared before they exist. Symlinks are not followed.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Whether `value` may fill a `{name}` placeholder of an output's `args`,
+/// i.e. is non-empty and only contains `[A-Za-z0-9_-]`.
+pub fn is_valid_path_param(value: &str) -> bool {
+    is_valid_slug(value)
+}
+
+/// Whether `slug` can be matched by `/output/:slug`, i.e. is non-empty and
+/// only contains `[A-Za-z0-9_-]`.
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Turns an output with a `matrix` into one output per value, with
+/// `{{ value }}` replaced in its slug, description and arguments.
+fn expand_matrix(output: OutputConfig) -> Vec<OutputConfig> {
+    if output.matrix.is_empty() {
+        return vec![output];
+    }
+
+    output
+        .matrix
+        .iter()
+        .map(|value| {
+            let substitute = |text: &str| text.replace("{{ value }}", value);
+            let mut expanded = output.clone();
+            expanded.matrix = Vec::new();
+            expanded.slug = substitute(&output.slug);
+            expanded.description = output.description.as_deref().map(substitute);
+            expanded.args = output
+                .args
+                .as_ref()
+                .map(|args| args.iter().map(|arg| substitute(arg)).collect());
+            for fallback in &mut expanded.fallbacks {
+                fallback.args = fallback.args.iter().map(|arg| substitute(arg)).collect();
+            }
+            expanded
+        })
+        .collect()
+}
+
+/// Fills in `cmd`, `args` and `env` variables left unset on `output` from its
+/// chain of bases, nearest base first.
+fn apply_bases(
+    output: &mut OutputConfig,
+    bases: &HashMap<String, OutputBase>,
+) -> Result<(), ResolvedConfigError> {
+    let mut seen = HashSet::new();
+    let mut next = output.base.clone();
+
+    while let Some(name) = next {
+        if !seen.insert(name.clone()) {
+            return Err(ResolvedConfigError::BaseCycle(name));
+        }
+        let base = bases
+            .get(&name)
+            .ok_or_else(|| ResolvedConfigError::MissingBase(output.slug.clone(), name.clone()))?;
+
+        if output.cmd.is_none()
+            && output.script.is_none()
+            && output.shell.is_none()
+            && output.proxy_url.is_none()
+        {
+            output.cmd = base.cmd.clone();
+        }
+        if output.args.is_none() {
+            output.args = base.args.clone();
+        }
+        for (key, value) in &base.env {
+            output
+                .env
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        next = base.base.clone();
+    }
+
+    Ok(())
+}
+
+/// Loosely checks that `value` looks like `type/subtype`, optionally followed
+/// by `;`-separated parameters.
+pub(crate) fn is_valid_mime(value: &str) -> bool {
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+
+    let mut parts = value.split(';');
+    let essence = parts.next().unwrap_or_default().trim();
+    let Some((kind, subtype)) = essence.split_once('/') else {
+        return false;
+    };
+    is_token(kind)
+        && is_token(subtype)
+        && parts.all(|param| {
+            param
+                .split_once('=')
+                .is_some_and(|(name, value)| is_token(name.trim()) && !value.trim().is_empty())
+        })
+        && !value.chars().any(|c| c.is_ascii_control())
+}
+
+/// Checks the arguments of the output's command and its fallbacks against the
+/// configured limits.
+fn check_arg_limits(
+    output: &OutputConfig,
+    max_args: Option<usize>,
+    max_arg_length: Option<usize>,
+) -> Result<(), ResolvedConfigError> {
+    let arg_lists = output
+        .args
+        .iter()
+        .chain(output.fallbacks.iter().map(|fallback| &fallback.args));
+
+    for args in arg_lists {
+        if let Some(max_args) = max_args {
+            if args.len() > max_args {
+                return Err(ResolvedConfigError::TooManyArgs(
+                    output.slug.clone(),
+                    args.len(),
+                    max_args,
+                ));
+            }
+        }
+        if let Some(max_arg_length) = max_arg_length {
+            if args.iter().any(|arg| arg.len() > max_arg_length) {
+                return Err(ResolvedConfigError::ArgTooLong(
+                    output.slug.clone(),
+                    max_arg_length,
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_script(script: &Path) -> Result<(), ResolvedConfigError> {
+    let metadata = std::fs::metadata(script)
+        .map_err(|_| ResolvedConfigError::ScriptNotFound(script.to_path_buf()))?;
+    if !metadata.is_file() {
+        return Err(ResolvedConfigError::ScriptNotFound(script.to_path_buf()));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(ResolvedConfigError::ScriptNotExecutable(
+                script.to_path_buf(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -87,14 +975,16 @@ mod tests {
     fn sample_output_config() -> OutputConfig {
         OutputConfig {
             slug: "test-output".to_string(),
-            cmd: "echo".to_string(),
-            args: vec!["hello".to_string()],
+            cmd: Some("echo".to_string()),
+            args: Some(vec!["hello".to_string()]),
+            ..Default::default()
         }
     }
 
     fn sample_config() -> Config {
         Config {
             outputs: vec![sample_output_config()],
+            ..Default::default()
         }
     }
 
@@ -102,8 +992,9 @@ mod tests {
     fn test_output_config_get_command_parts() {
         let output = OutputConfig {
             slug: "test".to_string(),
-            cmd: "ls".to_string(),
-            args: vec!["-la".to_string(), "/tmp".to_string()],
+            cmd: Some("ls".to_string()),
+            args: Some(vec!["-la".to_string(), "/tmp".to_string()]),
+            ..Default::default()
         };
 
         let (cmd, args) = output.get_command_parts();
@@ -123,8 +1014,10 @@ outputs:
         let config = Config::from_yaml_str(yaml).unwrap();
         assert_eq!(config.outputs.len(), 1);
         assert_eq!(config.outputs[0].slug, "test");
-        assert_eq!(config.outputs[0].cmd, "echo");
-        assert_eq!(config.outputs[0].args, vec!["hello", "world"]);
+        assert_eq!(config.outputs[0].cmd.as_deref(), Some("echo"));
+        assert_eq!(config.outputs[0].args.as_deref().unwrap(), vec![
+            "hello", "world"
+        ]);
     }
 
     #[test]
@@ -152,15 +1045,18 @@ outputs:
             outputs: vec![
                 OutputConfig {
                     slug: "duplicate".to_string(),
-                    cmd: "echo".to_string(),
-                    args: vec!["first".to_string()],
+                    cmd: Some("echo".to_string()),
+                    args: Some(vec!["first".to_string()]),
+                    ..Default::default()
                 },
                 OutputConfig {
                     slug: "duplicate".to_string(),
-                    cmd: "echo".to_string(),
-                    args: vec!["second".to_string()],
+                    cmd: Some("echo".to_string()),
+                    args: Some(vec!["second".to_string()]),
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         };
         let data_dir = PathBuf::from("/test/data");
 
@@ -172,6 +1068,384 @@ outputs:
         ));
     }
 
+    fn resolve_working_dir(working_dir: &str) -> Result<ResolvedConfig, ResolvedConfigError> {
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "pwd".to_string(),
+                cmd: Some("pwd".to_string()),
+                working_dir: Some(PathBuf::from(working_dir)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        ResolvedConfig::new(config, PathBuf::from("/test/data"))
+    }
+
+    #[test]
+    fn test_resolved_config_working_dir() {
+        let resolved = resolve_working_dir("scripts/./reports").unwrap();
+        let output = resolved.get_output_by_slug("pwd").unwrap();
+        assert_eq!(
+            resolved.command_dir(output),
+            Path::new("/test/data/scripts/reports")
+        );
+
+        let resolved = resolve_working_dir("/test/data/scripts").unwrap();
+        let output = resolved.get_output_by_slug("pwd").unwrap();
+        assert_eq!(
+            resolved.command_dir(output),
+            Path::new("/test/data/scripts")
+        );
+
+        for outside in [
+            "../other",
+            "scripts/../../other",
+            "/etc",
+            "/test/data/../other",
+        ] {
+            assert!(matches!(
+                resolve_working_dir(outside).unwrap_err(),
+                ResolvedConfigError::WorkingDirOutsideDataDir(slug, path)
+                    if slug == "pwd" && path == Path::new(outside)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let lookup = |name: &str| (name == "REGION").then(|| "eu-west-1".to_string());
+        assert_eq!(
+            interpolate_env("s3.${REGION}.amazonaws.com/${REGION}", lookup).unwrap(),
+            "s3.eu-west-1.amazonaws.com/eu-west-1"
+        );
+        assert_eq!(interpolate_env("$REGION ${", lookup).unwrap(), "$REGION ${");
+        assert_eq!(
+            interpolate_env("${MISSING}", lookup).unwrap_err(),
+            "MISSING"
+        );
+    }
+
+    #[test]
+    fn test_resolved_config_interpolates_env() {
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "env".to_string(),
+                cmd: Some("env".to_string()),
+                env: HashMap::from([
+                    ("AWS_REGION".to_string(), "eu-west-1".to_string()),
+                    ("SEARCH_PATH".to_string(), "${PATH}".to_string()),
+                ]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test")).unwrap();
+        let env = &resolved.get_output_by_slug("env").unwrap().env;
+        assert_eq!(env["AWS_REGION"], "eu-west-1");
+        assert_eq!(env["SEARCH_PATH"], std::env::var("PATH").unwrap());
+
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "env".to_string(),
+                cmd: Some("env".to_string()),
+                env: HashMap::from([(
+                    "TOKEN".to_string(),
+                    "${JUNCTION_TEST_UNDEFINED_VAR}".to_string(),
+                )]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            ResolvedConfig::new(config, PathBuf::from("/test")).unwrap_err(),
+            ResolvedConfigError::UndefinedEnvVar(slug, name)
+                if slug == "env" && name == "JUNCTION_TEST_UNDEFINED_VAR"
+        ));
+    }
+
+    #[test]
+    fn test_resolved_config_invalid_slug() {
+        for slug in ["a/b", "has space", ""] {
+            let config = Config {
+                outputs: vec![OutputConfig {
+                    slug: slug.to_string(),
+                    cmd: Some("echo".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            assert!(matches!(
+                ResolvedConfig::new(config, PathBuf::from("/test")).unwrap_err(),
+                ResolvedConfigError::InvalidSlug(invalid) if invalid == slug
+            ));
+        }
+    }
+
+    #[test]
+    fn test_resolved_config_valid_slug() {
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "Disk_usage-2".to_string(),
+                cmd: Some("df".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test")).unwrap();
+        assert!(resolved.get_output_by_slug("Disk_usage-2").is_some());
+    }
+
+    #[test]
+    fn test_resolved_config_expands_matrix() {
+        let yaml = r#"
+outputs:
+  - slug: "status-{{ value }}"
+    description: "Status of {{ value }}"
+    cmd: "curl"
+    args: ["https://{{ value }}.example.com/status"]
+    matrix: ["eu", "us", "ap"]
+"#;
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test")).unwrap();
+        assert_eq!(resolved.outputs.len(), 3);
+        for region in ["eu", "us", "ap"] {
+            let output = resolved
+                .get_output_by_slug(&format!("status-{region}"))
+                .unwrap();
+            assert_eq!(output.args.as_deref().unwrap(), vec![format!(
+                "https://{region}.example.com/status"
+            )]);
+            assert_eq!(
+                output.description.as_deref(),
+                Some(format!("Status of {region}").as_str())
+            );
+            assert!(output.matrix.is_empty());
+        }
+
+        // Without `{{ value }}` in the slug, every expansion gets the same slug
+        let yaml = r#"
+outputs:
+  - slug: "status"
+    cmd: "curl"
+    args: ["https://{{ value }}.example.com/status"]
+    matrix: ["eu", "us"]
+"#;
+        let config = Config::from_yaml_str(yaml).unwrap();
+        assert!(matches!(
+            ResolvedConfig::new(config, PathBuf::from("/test")).unwrap_err(),
+            ResolvedConfigError::DuplicatePublicKey(slug) if slug == "status"
+        ));
+    }
+
+    #[test]
+    fn test_resolved_config_inherits_base() {
+        let yaml = r#"
+bases:
+  curl:
+    cmd: "curl"
+    args: ["-s", "https://example.com"]
+  curl-json:
+    base: "curl"
+    args: ["-s", "-H", "Accept: application/json", "https://example.com"]
+outputs:
+  - slug: "page"
+    base: "curl"
+  - slug: "api"
+    base: "curl-json"
+  - slug: "other"
+    base: "curl"
+    args: ["-s", "https://example.org"]
+"#;
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test/data")).unwrap();
+
+        let page = resolved.get_output_by_slug("page").unwrap();
+        assert_eq!(page.cmd.as_deref(), Some("curl"));
+        assert_eq!(page.args.as_deref().unwrap(), vec![
+            "-s",
+            "https://example.com"
+        ]);
+
+        let api = resolved.get_output_by_slug("api").unwrap();
+        assert_eq!(api.cmd.as_deref(), Some("curl"));
+        assert_eq!(api.args.as_deref().unwrap(), vec![
+            "-s",
+            "-H",
+            "Accept: application/json",
+            "https://example.com"
+        ]);
+
+        let other = resolved.get_output_by_slug("other").unwrap();
+        assert_eq!(other.cmd.as_deref(), Some("curl"));
+        assert_eq!(other.args.as_deref().unwrap(), vec![
+            "-s",
+            "https://example.org"
+        ]);
+    }
+
+    #[test]
+    fn test_resolved_config_base_env_and_empty_args() {
+        let yaml = r#"
+bases:
+  curl:
+    cmd: "curl"
+    args: ["-s", "https://example.com"]
+    env:
+      REGION: "eu"
+      TOKEN: "base"
+  curl-us:
+    base: "curl"
+    env:
+      REGION: "us"
+outputs:
+  - slug: "api"
+    base: "curl-us"
+    env:
+      TOKEN: "own"
+  - slug: "bare"
+    base: "curl"
+    args: []
+"#;
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test/data")).unwrap();
+
+        let api = resolved.get_output_by_slug("api").unwrap();
+        assert_eq!(api.args.as_deref().unwrap(), vec![
+            "-s",
+            "https://example.com"
+        ]);
+        assert_eq!(api.env["REGION"], "us");
+        assert_eq!(api.env["TOKEN"], "own");
+
+        let bare = resolved.get_output_by_slug("bare").unwrap();
+        assert_eq!(bare.get_command_parts(), ("curl".to_string(), vec![]));
+        assert_eq!(bare.env["REGION"], "eu");
+    }
+
+    #[test]
+    fn test_resolved_config_missing_base() {
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "orphan".to_string(),
+                base: Some("nope".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let result = ResolvedConfig::new(config, PathBuf::from("/test/data"));
+        assert!(matches!(
+            result.unwrap_err(),
+            ResolvedConfigError::MissingBase(slug, base) if slug == "orphan" && base == "nope"
+        ));
+    }
+
+    #[test]
+    fn test_resolved_config_base_cycle() {
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "loop".to_string(),
+                base: Some("a".to_string()),
+                ..Default::default()
+            }],
+            bases: HashMap::from([
+                ("a".to_string(), OutputBase {
+                    base: Some("b".to_string()),
+                    ..Default::default()
+                }),
+                ("b".to_string(), OutputBase {
+                    base: Some("a".to_string()),
+                    ..Default::default()
+                }),
+            ]),
+            ..Default::default()
+        };
+
+        let result = ResolvedConfig::new(config, PathBuf::from("/test/data"));
+        assert!(matches!(
+            result.unwrap_err(),
+            ResolvedConfigError::BaseCycle(base) if base == "a"
+        ));
+    }
+
+    #[test]
+    fn test_resolved_config_extends_allowed_query_params() {
+        let config = Config {
+            outputs: vec![OutputConfig {
+                slug: "report".to_string(),
+                cmd: Some("echo".to_string()),
+                allow_query_env: true,
+                allowed_query_params: vec!["region".to_string(), "format".to_string()],
+                ..Default::default()
+            }],
+            allowed_query_params: vec!["format".to_string(), "lang".to_string()],
+            ..Default::default()
+        };
+
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test/data")).unwrap();
+        let output = resolved.get_output_by_slug("report").unwrap();
+        assert_eq!(output.allowed_query_params, vec![
+            "region", "format", "lang"
+        ]);
+    }
+
+    #[test]
+    fn test_resolved_config_arg_limits() {
+        let config = |args: Vec<&str>| Config {
+            outputs: vec![OutputConfig {
+                slug: "generated".to_string(),
+                cmd: Some("echo".to_string()),
+                args: Some(args.into_iter().map(str::to_string).collect()),
+                ..Default::default()
+            }],
+            max_args: Some(2),
+            max_arg_length: Some(5),
+            ..Default::default()
+        };
+        let data_dir = PathBuf::from("/test/data");
+
+        assert!(ResolvedConfig::new(config(vec!["a", "hello"]), data_dir.clone()).is_ok());
+        assert!(matches!(
+            ResolvedConfig::new(config(vec!["a", "b", "c"]), data_dir.clone()).unwrap_err(),
+            ResolvedConfigError::TooManyArgs(slug, 3, 2) if slug == "generated"
+        ));
+        assert!(matches!(
+            ResolvedConfig::new(config(vec!["hello!"]), data_dir).unwrap_err(),
+            ResolvedConfigError::ArgTooLong(slug, 5) if slug == "generated"
+        ));
+    }
+
+    #[test]
+    fn test_resolved_config_invalid_content_type() {
+        for content_type in [
+            "json",
+            "application/",
+            "text/plain; charset",
+            "text/plain\n",
+        ] {
+            let config = Config {
+                outputs: vec![OutputConfig {
+                    slug: "typed".to_string(),
+                    cmd: Some("echo".to_string()),
+                    content_type: Some(content_type.to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+
+            let result = ResolvedConfig::new(config, PathBuf::from("/test/data"));
+            assert!(matches!(
+                result.unwrap_err(),
+                ResolvedConfigError::InvalidContentType(slug, value)
+                    if slug == "typed" && value == content_type
+            ));
+        }
+
+        assert!(is_valid_mime("application/json"));
+        assert!(is_valid_mime("text/csv; charset=utf-8"));
+        assert!(is_valid_mime("application/vnd.api+json"));
+    }
+
     #[test]
     fn test_resolved_config_get_output_by_slug() {
         let config = sample_config();
@@ -188,11 +1462,88 @@ outputs:
 
     #[test]
     fn test_config_from_yaml_file_not_found() {
-        let result = Config::from_yaml_file("/nonexistent/file.yaml");
+        let result = Config::from_yaml_file("/nonexistent/file.yaml", DEFAULT_MAX_CONFIG_SIZE);
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err(), RawConfigError::IoError(_)));
     }
 
+    #[test]
+    fn test_config_from_yaml_file_too_large() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.yaml");
+        std::fs::write(&path, format!("outputs: []\n#{}\n", "x".repeat(2048))).unwrap();
+
+        let err = Config::from_yaml_file(&path, 1024).unwrap_err();
+        assert!(matches!(err, RawConfigError::TooLarge(2062, 1024)));
+        assert_eq!(
+            err.to_string(),
+            "Config file is 2062 bytes, more than the maximum of 1024 bytes"
+        );
+
+        assert!(Config::from_yaml_file(&path, 4096).is_ok());
+    }
+
+    #[test]
+    fn test_config_from_file_formats() {
+        let yaml = r#"
+max_connections: 8
+outputs:
+  - slug: "report"
+    cmd: "/bin/echo"
+    args: ["hello", "world"]
+    cache_ttl_secs: 60
+    empty_output: 204
+"#;
+        let toml = r#"
+max_connections = 8
+
+[[outputs]]
+slug = "report"
+cmd = "/bin/echo"
+args = ["hello", "world"]
+cache_ttl_secs = 60
+empty_output = 204
+"#;
+        let json = r#"{
+  "max_connections": 8,
+  "outputs": [
+    {
+      "slug": "report",
+      "cmd": "/bin/echo",
+      "args": ["hello", "world"],
+      "cache_ttl_secs": 60,
+      "empty_output": 204
+    }
+  ]
+}"#;
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let load = |name: &str, content: &str| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            let config = Config::from_file(&path, DEFAULT_MAX_CONFIG_SIZE).unwrap();
+            serde_json::to_value(config).unwrap()
+        };
+
+        let from_yaml = load("config.yaml", yaml);
+        assert_eq!(from_yaml["outputs"][0]["empty_output"], "204");
+        assert_eq!(load("config.yml", yaml), from_yaml);
+        assert_eq!(load("config.toml", toml), from_yaml);
+        assert_eq!(load("config.json", json), from_yaml);
+
+        let path = temp_dir.path().join("broken.toml");
+        std::fs::write(&path, "outputs = [").unwrap();
+        assert!(matches!(
+            Config::from_file(&path, DEFAULT_MAX_CONFIG_SIZE).unwrap_err(),
+            RawConfigError::TomlParseError(_)
+        ));
+        let path = temp_dir.path().join("broken.json");
+        std::fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            Config::from_file(&path, DEFAULT_MAX_CONFIG_SIZE).unwrap_err(),
+            RawConfigError::JsonParseError(_)
+        ));
+    }
+
     #[test]
     fn test_multiple_outputs() {
         let yaml = r#"
@@ -214,12 +1565,12 @@ outputs:
         assert!(resolved.outputs.contains_key("second"));
 
         let first = resolved.get_output_by_slug("first").unwrap();
-        assert_eq!(first.cmd, "echo");
-        assert_eq!(first.args, vec!["first"]);
+        assert_eq!(first.cmd.as_deref(), Some("echo"));
+        assert_eq!(first.args.as_deref().unwrap(), vec!["first"]);
 
         let second = resolved.get_output_by_slug("second").unwrap();
-        assert_eq!(second.cmd, "ls");
-        assert_eq!(second.args, vec!["-la"]);
+        assert_eq!(second.cmd.as_deref(), Some("ls"));
+        assert_eq!(second.args.as_deref().unwrap(), vec!["-la"]);
     }
 
     #[test]
@@ -232,6 +1583,277 @@ outputs:
 "#;
 
         let config = Config::from_yaml_str(yaml).unwrap();
-        assert_eq!(config.outputs[0].args.len(), 0);
+        assert_eq!(config.outputs[0].args, Some(vec![]));
+    }
+
+    #[test]
+    fn test_resolved_config_script_relative_to_data_dir() {
+        let yaml = r#"
+outputs:
+  - slug: "script"
+    script: "scripts/run.sh"
+"#;
+
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test/data")).unwrap();
+
+        let (cmd, args) = resolved
+            .get_output_by_slug("script")
+            .unwrap()
+            .get_command_parts();
+        assert_eq!(cmd, "/test/data/scripts/run.sh");
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn test_resolved_config_cmd_and_script_exclusive() {
+        let both = r#"
+outputs:
+  - slug: "both"
+    cmd: "echo"
+    script: "run.sh"
+"#;
+        let neither = r#"
+outputs:
+  - slug: "neither"
+    args: ["hello"]
+"#;
+        let proxied = r#"
+outputs:
+  - slug: "proxied"
+    cmd: "echo"
+    proxy_url: "http://localhost:7749/output/other"
+"#;
+        let shell_and_cmd = r#"
+outputs:
+  - slug: "shell-and-cmd"
+    cmd: "echo"
+    shell: "echo hello | tr a-z A-Z"
+"#;
+
+        for (yaml, slug) in [
+            (both, "both"),
+            (neither, "neither"),
+            (proxied, "proxied"),
+            (shell_and_cmd, "shell-and-cmd"),
+        ] {
+            let config = Config::from_yaml_str(yaml).unwrap();
+            let result = ResolvedConfig::new(config, PathBuf::from("/test"));
+            assert!(matches!(
+                result.unwrap_err(),
+                ResolvedConfigError::InvalidCommand(s) if s == slug
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_commands_on_start() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let script_path = temp_dir.path().join("run.sh");
+        std::fs::write(&script_path, "#!/bin/sh\necho hello\n").unwrap();
+
+        let yaml = r#"
+verify_commands_on_start: true
+outputs:
+  - slug: "script"
+    script: "run.sh"
+"#;
+        let missing = r#"
+verify_commands_on_start: true
+outputs:
+  - slug: "script"
+    script: "missing.sh"
+"#;
+        let data_dir = temp_dir.path().to_path_buf();
+
+        let result = ResolvedConfig::new(Config::from_yaml_str(yaml).unwrap(), data_dir.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ResolvedConfigError::ScriptNotExecutable(_)
+        ));
+
+        let result = ResolvedConfig::new(Config::from_yaml_str(missing).unwrap(), data_dir.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ResolvedConfigError::ScriptNotFound(_)
+        ));
+
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let result = ResolvedConfig::new(Config::from_yaml_str(yaml).unwrap(), data_dir);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_output_fallbacks() {
+        let yaml = r#"
+outputs:
+  - slug: "resilient"
+    cmd: "primary"
+    fallbacks:
+      - cmd: "secondary"
+        args: ["--fast"]
+      - cmd: "tertiary"
+"#;
+
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let fallbacks = &config.outputs[0].fallbacks;
+        assert_eq!(fallbacks.len(), 2);
+        assert_eq!(fallbacks[0].cmd, "secondary");
+        assert_eq!(fallbacks[0].args, vec!["--fast"]);
+        assert!(fallbacks[1].args.is_empty());
+    }
+
+    #[test]
+    fn test_empty_output_modes() {
+        let yaml = r#"
+outputs:
+  - slug: "default"
+    cmd: "true"
+  - slug: "ok"
+    cmd: "true"
+    empty_output: ok
+  - slug: "no-content"
+    cmd: "true"
+    empty_output: 204
+  - slug: "no-content-quoted"
+    cmd: "true"
+    empty_output: "204"
+  - slug: "error"
+    cmd: "true"
+    empty_output: error
+"#;
+
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let modes: Vec<_> = config.outputs.iter().map(|o| o.empty_output).collect();
+        assert_eq!(modes, vec![
+            EmptyOutput::Ok,
+            EmptyOutput::Ok,
+            EmptyOutput::NoContent,
+            EmptyOutput::NoContent,
+            EmptyOutput::Error,
+        ]);
+
+        let invalid = r#"
+outputs:
+  - slug: "invalid"
+    cmd: "true"
+    empty_output: 404
+"#;
+        assert!(matches!(
+            Config::from_yaml_str(invalid).unwrap_err(),
+            RawConfigError::ParseError(_)
+        ));
+    }
+
+    #[test]
+    fn test_enabled_flag_requires_flags_url() {
+        let yaml = r#"
+outputs:
+  - slug: "beta"
+    cmd: "echo"
+    enabled_flag: "beta"
+"#;
+
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let result = ResolvedConfig::new(config, PathBuf::from("/test"));
+        assert!(matches!(
+            result.unwrap_err(),
+            ResolvedConfigError::FlagsUnavailable(slug) if slug == "beta"
+        ));
+    }
+
+    #[test]
+    fn test_sample_config_parses() {
+        let config = Config::from_yaml_str(SAMPLE_CONFIG).unwrap();
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test")).unwrap();
+        assert!(resolved.get_output_by_slug("hello").is_some());
+    }
+
+    #[test]
+    fn test_write_sample_config() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let data_dir = temp_dir.path().join("data");
+
+        let path = write_sample_config(&data_dir, false).unwrap();
+        assert_eq!(path, data_dir.join("config.yaml"));
+        assert!(Config::from_yaml_file(&path, DEFAULT_MAX_CONFIG_SIZE).is_ok());
+
+        std::fs::write(&path, "outputs: []").unwrap();
+        let err = write_sample_config(&data_dir, false).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "outputs: []");
+
+        write_sample_config(&data_dir, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), SAMPLE_CONFIG);
+    }
+
+    #[test]
+    fn test_pty_with_stdin_url() {
+        let yaml = r#"
+outputs:
+  - slug: "interactive"
+    cmd: "cat"
+    pty: true
+    stdin_url: "http://127.0.0.1:7749/output/source"
+"#;
+
+        let config = Config::from_yaml_str(yaml).unwrap();
+        let result = ResolvedConfig::new(config, PathBuf::from("/test"));
+        assert!(matches!(
+            result.unwrap_err(),
+            ResolvedConfigError::PtyWithStdin(slug) if slug == "interactive"
+        ));
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let yaml = r#"
+max_connections: 64
+outputs: []
+"#;
+
+        let config = Config::from_yaml_str(yaml).unwrap();
+        assert_eq!(config.max_connections, Some(64));
+        assert!(config.modify_path);
+
+        let resolved = ResolvedConfig::new(config, PathBuf::from("/test")).unwrap();
+        assert_eq!(resolved.max_connections, Some(64));
+    }
+
+    #[test]
+    fn test_path_params() {
+        let mut output = OutputConfig {
+            args: Some(vec![
+                "--user={id}".to_string(),
+                "{org}/{id}".to_string(),
+                "{print}".to_string(),
+            ]),
+            path_params: vec!["id".to_string(), "org".to_string()],
+            ..sample_output_config()
+        };
+        output.substitute_path_params(&[
+            ("id".to_string(), "42".to_string()),
+            ("org".to_string(), "acme".to_string()),
+        ]);
+        assert_eq!(output.args.as_deref().unwrap(), vec![
+            "--user=42",
+            "acme/42",
+            "{print}"
+        ]);
+
+        let config = Config {
+            outputs: vec![OutputConfig {
+                path_params: vec!["a b".to_string()],
+                ..sample_output_config()
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            ResolvedConfig::new(config, PathBuf::from("/test")),
+            Err(ResolvedConfigError::InvalidPathParam(_, name)) if name == "a b"
+        ));
     }
 }
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long a flag value is reused before asking the flags service again.
+pub const DEFAULT_FLAG_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Evaluates feature flags against an HTTP flags service.
+///
+/// A flag is looked up with `GET <flags_url>/<flag>`, which must answer with a
+/// body of `true` or `false`. Any other answer, or a failed request, counts as
+/// the flag being off.
+#[derive(Default)]
+pub struct FlagClient {
+    client: reqwest::Client,
+    cache: Mutex<HashMap<String, (Instant, bool)>>,
+}
+
+impl FlagClient {
+    pub async fn is_enabled(&self, flags_url: &str, flag: &str, cache_ttl: Duration) -> bool {
+        if let Some((fetched_at, enabled)) = self.cache.lock().unwrap().get(flag) {
+            if fetched_at.elapsed() < cache_ttl {
+                return *enabled;
+            }
+        }
+
+        let enabled = match self.fetch(flags_url, flag).await {
+            Ok(enabled) => enabled,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to evaluate flag {}, treating it as off: {}",
+                    flag,
+                    e
+                );
+                false
+            }
+        };
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(flag.to_string(), (Instant::now(), enabled));
+        enabled
+    }
+
+    async fn fetch(&self, flags_url: &str, flag: &str) -> Result<bool, reqwest::Error> {
+        let url = format!("{}/{}", flags_url.trim_end_matches('/'), flag);
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(body.trim() == "true")
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// How long finished jobs are kept around for polling.
+pub const DEFAULT_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Job {
+    pub id: String,
+    pub slug: String,
+    pub status: JobStatus,
+    /// The output body on success, or the error message on failure.
+    pub result: Option<String>,
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+/// In-memory store of background output jobs. Finished jobs are dropped once
+/// they are older than the TTL.
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    ttl: Duration,
+}
+
+impl Default for JobStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOB_TTL)
+    }
+}
+
+impl JobStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Registers a new running job for `slug` and returns its ID.
+    pub fn create(&self, slug: &str) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut jobs = self.jobs.lock().unwrap();
+        self.remove_expired(&mut jobs);
+        jobs.insert(id.clone(), Job {
+            id: id.clone(),
+            slug: slug.to_string(),
+            status: JobStatus::Running,
+            result: None,
+            finished_at: None,
+        });
+        id
+    }
+
+    pub fn finish(&self, id: &str, result: Result<String, String>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(id) {
+            (job.status, job.result) = match result {
+                Ok(output) => (JobStatus::Succeeded, Some(output)),
+                Err(err) => (JobStatus::Failed, Some(err)),
+            };
+            job.finished_at = Some(Instant::now());
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.remove_expired(&mut jobs);
+        jobs.get(id).cloned()
+    }
+
+    fn remove_expired(&self, jobs: &mut HashMap<String, Job>) {
+        jobs.retain(|_, job| {
+            job.finished_at
+                .is_none_or(|finished_at| finished_at.elapsed() < self.ttl)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let store = JobStore::default();
+        let id = store.create("report");
+
+        let job = store.get(&id).unwrap();
+        assert_eq!(job.slug, "report");
+        assert_eq!(job.status, JobStatus::Running);
+        assert!(job.result.is_none());
+
+        store.finish(&id, Ok("done".to_string()));
+        let job = store.get(&id).unwrap();
+        assert_eq!(job.status, JobStatus::Succeeded);
+        assert_eq!(job.result.as_deref(), Some("done"));
+
+        assert!(store.get("unknown").is_none());
+    }
+
+    #[test]
+    fn test_finished_jobs_expire() {
+        let store = JobStore::new(Duration::ZERO);
+        let running = store.create("slow");
+        let finished = store.create("fast");
+        store.finish(&finished, Err("failed".to_string()));
+
+        assert!(store.get(&finished).is_none());
+        assert!(store.get(&running).is_some());
+    }
+}
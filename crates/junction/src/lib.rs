@@ -1,12 +1,27 @@
+mod auth;
 mod config;
+#[cfg(feature = "flags")]
+mod flags;
+mod jobs;
+mod listener;
 mod server;
+#[cfg(test)]
+mod test_util;
 
+pub use config::write_sample_config;
 pub use config::Config;
+pub use config::ConfigFile;
 pub use config::ResolvedConfig;
+pub use config::DEFAULT_MAX_CONFIG_SIZE;
+pub use server::json_banner;
 pub use server::serve;
 
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 const GIT_VERSION: &str = git_version::git_version!();
+/// Seconds since the Unix epoch when the build script last ran, i.e. when a
+/// file of the package last changed before a build.
+const BUILD_TIMESTAMP: &str = env!("JUNCTION_BUILD_TIMESTAMP");
+const RUSTC_VERSION: &str = env!("JUNCTION_RUSTC_VERSION");
 static VERSION: std::sync::OnceLock<String> = std::sync::OnceLock::new();
 pub fn version() -> &'static str {
     VERSION
@@ -0,0 +1,172 @@
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use poem::http::uri::Scheme;
+use poem::listener::Acceptor;
+use poem::listener::Listener;
+use poem::web::LocalAddr;
+use poem::web::RemoteAddr;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+/// A listener that caps the number of connections open at the same time.
+///
+/// Once the limit is reached, no further connections are accepted until one of
+/// the open connections is closed, so new clients queue in the socket backlog.
+pub struct ConnectionLimitListener<L> {
+    inner: L,
+    max_connections: Option<usize>,
+}
+
+impl<L> ConnectionLimitListener<L> {
+    pub fn new(inner: L, max_connections: Option<usize>) -> Self {
+        Self {
+            inner,
+            max_connections,
+        }
+    }
+}
+
+impl<L: Listener> Listener for ConnectionLimitListener<L> {
+    type Acceptor = ConnectionLimitAcceptor<L::Acceptor>;
+
+    async fn into_acceptor(self) -> Result<Self::Acceptor> {
+        let inner = self.inner.into_acceptor().await?;
+        Ok(ConnectionLimitAcceptor::new(inner, self.max_connections))
+    }
+}
+
+pub struct ConnectionLimitAcceptor<A> {
+    inner: A,
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl<A> ConnectionLimitAcceptor<A> {
+    pub fn new(inner: A, max_connections: Option<usize>) -> Self {
+        Self {
+            inner,
+            semaphore: max_connections.map(|limit| Arc::new(Semaphore::new(limit))),
+        }
+    }
+}
+
+impl<A: Acceptor> Acceptor for ConnectionLimitAcceptor<A> {
+    type Io = LimitedIo<A::Io>;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.inner.local_addr()
+    }
+
+    async fn accept(&mut self) -> Result<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        let permit = match &self.semaphore {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("Connection semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        let (io, local_addr, remote_addr, scheme) = self.inner.accept().await?;
+        Ok((
+            LimitedIo {
+                inner: io,
+                _permit: permit,
+            },
+            local_addr,
+            remote_addr,
+            scheme,
+        ))
+    }
+}
+
+/// A connection holding one slot of the connection limit until it is dropped.
+pub struct LimitedIo<T> {
+    inner: T,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for LimitedIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for LimitedIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use poem::listener::TcpListener;
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connection_limit_is_respected() {
+        let listener = ConnectionLimitListener::new(TcpListener::bind("127.0.0.1:0"), Some(2));
+        let mut acceptor = listener.into_acceptor().await.unwrap();
+        let addr = acceptor.local_addr()[0].as_socket_addr().cloned().unwrap();
+
+        let mut clients = Vec::new();
+        for _ in 0..5 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+
+        let first = acceptor.accept().await.unwrap();
+        let _second = acceptor.accept().await.unwrap();
+
+        // The limit is reached, so the remaining connections must wait
+        let third = tokio::time::timeout(Duration::from_millis(200), acceptor.accept()).await;
+        assert!(third.is_err());
+
+        // Closing one connection frees a slot for the next one
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(5), acceptor.accept()).await;
+        assert!(third.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_no_connection_limit() {
+        let listener = ConnectionLimitListener::new(TcpListener::bind("127.0.0.1:0"), None);
+        let mut acceptor = listener.into_acceptor().await.unwrap();
+        let addr = acceptor.local_addr()[0].as_socket_addr().cloned().unwrap();
+
+        let mut clients = Vec::new();
+        let mut accepted = Vec::new();
+        for _ in 0..5 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+            accepted.push(acceptor.accept().await.unwrap());
+        }
+        assert_eq!(accepted.len(), 5);
+    }
+}
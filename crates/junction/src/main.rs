@@ -2,6 +2,7 @@ use std::fs;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::value_parser;
 use clap::Arg;
@@ -20,7 +21,7 @@ fn parse_args() -> Command {
                 .num_args(1)
                 .default_value("0.0.0.0:7749")
                 .action(ArgAction::Set)
-                .help("API listen address"),
+                .help("API listen address, or a comma-separated list of addresses"),
         )
         .arg(
             Arg::new("DATA_DIR")
@@ -28,6 +29,7 @@ fn parse_args() -> Command {
                 .env("JUNCTION_DATA_DIR")
                 .num_args(1)
                 .default_value("./data")
+                .global(true)
                 .action(ArgAction::Set)
                 .help("Will set this path as the runtime directory for commands"),
         )
@@ -39,32 +41,107 @@ fn parse_args() -> Command {
                 .default_value("./data/config.yaml")
                 .value_parser(value_parser!(PathBuf))
                 .action(ArgAction::Set)
-                .help("Path to config file (YAML format)"),
+                .help("Path to config file (YAML, or TOML or JSON by extension)"),
+        )
+        .arg(
+            Arg::new("MAX_CONFIG_SIZE")
+                .long("max-config-size")
+                .env("JUNCTION_MAX_CONFIG_SIZE")
+                .num_args(1)
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set)
+                .help("Refuse config files larger than this many bytes [default: 10 MiB]"),
+        )
+        .arg(
+            Arg::new("SHUTDOWN_TIMEOUT")
+                .long("shutdown-timeout")
+                .env("JUNCTION_SHUTDOWN_TIMEOUT")
+                .num_args(1)
+                .default_value("30")
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set)
+                .help("Seconds to let in-flight requests finish when shutting down"),
+        )
+        .arg(
+            Arg::new("COMPRESSION")
+                .long("compression")
+                .env("JUNCTION_COMPRESSION")
+                .num_args(1)
+                .default_value("true")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::Set)
+                .help("Compress responses for clients sending Accept-Encoding"),
+        )
+        .arg(
+            Arg::new("LOG_FORMAT")
+                .long("log-format")
+                .env("JUNCTION_LOG_FORMAT")
+                .num_args(1)
+                .default_value("text")
+                .value_parser(["text", "json"])
+                .global(true)
+                .action(ArgAction::Set)
+                .help("Log format; json emits one object per line with span fields"),
+        )
+        .arg(
+            Arg::new("BANNER")
+                .long("banner")
+                .env("JUNCTION_BANNER")
+                .num_args(1)
+                .value_parser(["json"])
+                .action(ArgAction::Set)
+                .help("Print a machine-readable startup banner to stdout"),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Write a sample config.yaml to the data directory")
+                .arg(
+                    Arg::new("FORCE")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite an existing config"),
+                ),
         )
 }
 
 #[tokio::main]
 async fn main() {
+    let args = parse_args().get_matches();
+
+    let fmt_layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let fmt_layer = match args.get_one::<String>("LOG_FORMAT").map(String::as_str) {
+        Some("json") => fmt_layer.json().boxed(),
+        _ => fmt_layer.boxed(),
+    };
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::fmt::layer()
-                .with_writer(std::io::stderr)
-                .with_filter(
-                    tracing_subscriber::EnvFilter::builder()
-                        .with_default_directive(
-                            tracing_subscriber::filter::LevelFilter::INFO.into(),
-                        )
-                        .from_env_lossy(),
-                ),
+            fmt_layer.with_filter(
+                tracing_subscriber::EnvFilter::builder()
+                    .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
+                    .from_env_lossy(),
+            ),
         )
         .init();
 
-    let args = parse_args().get_matches();
+    if let Some(("init", init_args)) = args.subcommand() {
+        let data_dir = Path::new(args.get_one::<String>("DATA_DIR").unwrap());
+        let force = init_args.get_flag("FORCE");
+        match junction::write_sample_config(data_dir, force) {
+            Ok(path) => tracing::info!("Wrote sample config to {}", path.display()),
+            Err(e) => {
+                tracing::error!("Failed to write sample config: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let api_addr = args
+    let api_addrs = args
         .get_one::<String>("API_ADDR")
         .unwrap()
-        .parse::<SocketAddr>()
+        .split(',')
+        .map(|addr| addr.trim().parse::<SocketAddr>())
+        .collect::<Result<Vec<_>, _>>()
         .expect("Invalid API address");
 
     let data_dir = Path::new(args.get_one::<String>("DATA_DIR").unwrap());
@@ -74,11 +151,53 @@ async fn main() {
     }
 
     let config_file_path = args.get_one::<PathBuf>("CONFIG_FILE").unwrap();
-    let config = junction::Config::from_yaml_file(config_file_path).expect("Failed to load config");
-    let resolved_config = junction::ResolvedConfig::new(config, data_dir.to_path_buf())
-        .expect("Failed to resolve config");
+    let max_config_size = args
+        .get_one::<u64>("MAX_CONFIG_SIZE")
+        .copied()
+        .unwrap_or(junction::DEFAULT_MAX_CONFIG_SIZE);
+    // Loaded the same way `POST /reload` and SIGHUP reload it
+    let resolved_config = junction::ConfigFile {
+        path: config_file_path.clone(),
+        max_size: max_config_size,
+    }
+    .load(data_dir.to_path_buf())
+    .expect("Failed to load config");
+
+    if args
+        .get_one::<String>("BANNER")
+        .is_some_and(|banner| banner == "json")
+    {
+        println!("{}", junction::json_banner(&api_addrs, &resolved_config));
+    }
+
+    let shutdown_timeout = Duration::from_secs(*args.get_one::<u64>("SHUTDOWN_TIMEOUT").unwrap());
+    let compression = *args.get_one::<bool>("COMPRESSION").unwrap();
+    junction::serve(
+        &api_addrs,
+        resolved_config,
+        Some(shutdown_timeout),
+        compression,
+    )
+    .await
+    .expect("Failed to start the server");
+}
 
-    junction::serve(api_addr, resolved_config)
-        .await
-        .expect("Failed to start the server");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_format() {
+        let args = parse_args().try_get_matches_from(["junction"]).unwrap();
+        assert_eq!(args.get_one::<String>("LOG_FORMAT").unwrap(), "text");
+
+        let args = parse_args()
+            .try_get_matches_from(["junction", "--log-format", "json"])
+            .unwrap();
+        assert_eq!(args.get_one::<String>("LOG_FORMAT").unwrap(), "json");
+
+        assert!(parse_args()
+            .try_get_matches_from(["junction", "--log-format", "xml"])
+            .is_err());
+    }
 }
@@ -1,266 +1,5095 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
+use arc_swap::ArcSwap;
+use futures_util::StreamExt;
+use futures_util::TryStreamExt;
+use http_body::Frame;
+use http_body_util::combinators::BoxBody;
+use http_body_util::StreamBody;
 use poem::get;
 use poem::handler;
+use poem::http::Method;
+use poem::http::Uri;
+use poem::listener::Acceptor;
+use poem::listener::BoxListener;
+use poem::listener::Listener;
 use poem::listener::TcpListener;
 use poem::middleware::AddData;
+use poem::middleware::Compression;
 use poem::middleware::Cors;
+use poem::post;
 use poem::web::Data;
 use poem::web::Json;
 use poem::web::Path;
+use poem::web::Query;
+use poem::Body;
 use poem::Endpoint;
 use poem::EndpointExt;
+use poem::IntoResponse;
+use poem::Request;
 use poem::Response;
 use poem::Result;
 use poem::Route;
 use poem::Server;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
 use tokio::process::Command;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
+use crate::auth::ApiKeyAuth;
+use crate::config::is_valid_mime;
+use crate::config::is_valid_path_param;
+use crate::config::EmptyOutput;
+use crate::config::LineEndings;
+use crate::config::Minify;
+use crate::config::OutputConfig;
 use crate::config::ResolvedConfig;
+use crate::config::StreamErrorMode;
+use crate::jobs::Job;
+use crate::jobs::JobStore;
+use crate::listener::ConnectionLimitListener;
 
-pub fn app(config: ResolvedConfig) -> impl Endpoint {
-    Route::new()
+/// Header identifying a request in the logs, echoed in its response.
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The API, serving whichever config is current in `config` and compressing
+/// responses for clients that accept it if `compression` is set.
+fn app(
+    config: Arc<ArcSwap<ResolvedConfig>>,
+    state: Arc<RuntimeState>,
+    compression: bool,
+) -> impl Endpoint {
+    let current = config.load();
+    let api_key = current.api_key.clone();
+    let mut route = Route::new();
+    // Reloading runs arbitrary new commands, so it is never left unprotected
+    if api_key.is_some() {
+        route = route
+            .at("/reload", post(reload_config))
+            .at("/cache/invalidate", post(invalidate_cache));
+    }
+    route
         .at("/config", get(get_config))
-        .at("/output/:slug", get(get_output))
+        .at("/outputs", get(get_outputs))
+        .at("/help", get(get_help))
+        .at("/output/:slug", get(get_output).post(post_output))
+        .at("/output/:slug/*params", get(get_output_with_params))
+        .at(
+            "/output/:slug/async",
+            get(get_output_with_async_param).post(start_output_job),
+        )
+        .at("/jobs/:id", get(get_job))
+        .at("/status", get(get_status))
+        .at("/version", get(get_version))
+        .at("/rpc", post(rpc))
+        .with_if(compression, Compression::new())
+        .around(keep_stream_trailers)
+        .with(ApiKeyAuth::new(api_key))
         .with(Cors::new())
-        .with(AddData::new(Arc::new(config)))
+        .around(with_current_config)
+        .with(AddData::new(config))
+        .with(AddData::new(state))
+        .around(with_request_id)
+}
+
+/// Runs the request in a span carrying its `X-Request-Id`, generating one if
+/// the client didn't send it, and echoes the id in the response.
+async fn with_request_id<E: Endpoint>(ep: Arc<E>, req: Request) -> Result<Response> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .filter(|value| !value.is_empty())
+        .cloned()
+        .unwrap_or_else(|| {
+            poem::http::HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+                .expect("UUIDs are valid header values")
+        });
+    let span = tracing::info_span!(
+        "request",
+        request_id = %String::from_utf8_lossy(request_id.as_bytes()),
+    );
+
+    let mut resp = ep.get_response(req).instrument(span).await;
+    resp.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    Ok(resp)
+}
+
+/// Makes the config that is current when a request arrives available to its
+/// handler as `Data<&Arc<ResolvedConfig>>`, so a reload never changes the
+/// config in the middle of a request.
+async fn with_current_config<E: Endpoint>(ep: Arc<E>, mut req: Request) -> Result<E::Output> {
+    if let Some(config) = req.data::<Arc<ArcSwap<ResolvedConfig>>>() {
+        let current = config.load_full();
+        req.extensions_mut().insert(current);
+    }
+    ep.call(req).await
+}
+
+/// Serves streamed outputs uncompressed to clients reading their
+/// `X-Exit-Code` trailer, i.e. that sent `TE: trailers`, because compressing
+/// a body drops its trailers.
+async fn keep_stream_trailers<E: Endpoint>(ep: Arc<E>, mut req: Request) -> Result<E::Output> {
+    let accepts_trailers = req
+        .headers()
+        .get_all(poem::http::header::TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| coding.trim().eq_ignore_ascii_case("trailers"));
+    let sends_trailers = accepts_trailers
+        && req
+            .uri()
+            .path()
+            .strip_prefix("/output/")
+            .and_then(|rest| rest.split('/').next())
+            .zip(req.extensions().get::<Arc<ResolvedConfig>>())
+            .and_then(|(slug, config)| config.get_output_by_slug(slug))
+            .is_some_and(|output| {
+                output.stream && output.stream_error_mode == StreamErrorMode::Trailer
+            });
+    if sends_trailers {
+        req.headers_mut()
+            .remove(poem::http::header::ACCEPT_ENCODING);
+    }
+    ep.call(req).await
+}
+
+#[handler]
+async fn reload_config(
+    config: Data<&Arc<ArcSwap<ResolvedConfig>>>,
+    state: Data<&Arc<RuntimeState>>,
+) -> Result<&'static str> {
+    reload(&config, &state).await?;
+    Ok("Config reloaded")
+}
+
+#[derive(Deserialize)]
+struct InvalidateCacheRequest {
+    slug: Option<String>,
+}
+
+#[derive(Serialize)]
+struct InvalidateCacheResponse {
+    invalidated: usize,
+}
+
+/// Drops the cached outputs of the slug given as `{"slug": "<slug>"}`, or of
+/// every output without a body.
+#[handler]
+async fn invalidate_cache(
+    state: Data<&Arc<RuntimeState>>,
+    body: Vec<u8>,
+) -> Result<Json<InvalidateCacheResponse>> {
+    let slug = if body.is_empty() {
+        None
+    } else {
+        serde_json::from_slice::<InvalidateCacheRequest>(&body)
+            .map_err(|e| {
+                poem::Error::from_string(e.to_string(), poem::http::StatusCode::BAD_REQUEST)
+            })?
+            .slug
+    };
+
+    let invalidated = state
+        .retain_cached_outputs(|cached| slug.as_deref().is_some_and(|slug| slug != cached))
+        .await;
+    tracing::info!(
+        "Invalidated {} cached outputs of {}",
+        invalidated,
+        slug.as_deref().unwrap_or("all outputs")
+    );
+    Ok(Json(InvalidateCacheResponse { invalidated }))
+}
+
+/// Re-reads the config file and swaps the new config in. If it can't be
+/// loaded, the error is logged and the current config stays in use.
+async fn reload(config: &ArcSwap<ResolvedConfig>, state: &RuntimeState) -> Result<()> {
+    let current = config.load();
+    let Some(config_file) = &current.config_file else {
+        tracing::error!("Failed to reload config: not loaded from a file");
+        return Err(poem::Error::from_string(
+            "Config was not loaded from a file",
+            poem::http::StatusCode::CONFLICT,
+        ));
+    };
+
+    let reloaded = config_file.load(current.data_dir.clone()).map_err(|e| {
+        tracing::error!("Failed to reload config: {}", e);
+        poem::Error::from_string(e.to_string(), poem::http::StatusCode::BAD_REQUEST)
+    })?;
+    tracing::info!("Reloaded config from {}", config_file.path.display());
+
+    // Cached outputs stay valid as long as their definition and the settings
+    // every command runs with are unchanged
+    let same_settings = current.same_command_settings(&reloaded);
+    state
+        .retain_cached_outputs(|slug| {
+            same_settings && current.get_output_by_slug(slug) == reloaded.get_output_by_slug(slug)
+        })
+        .await;
+    config.store(Arc::new(reloaded));
+    Ok(())
+}
+
+/// Reloads the config every time the process receives SIGHUP, until the
+/// returned task is aborted.
+#[cfg(unix)]
+fn spawn_reload_on_hangup(
+    config: Arc<ArcSwap<ResolvedConfig>>,
+    state: Arc<RuntimeState>,
+) -> std::io::Result<tokio::task::JoinHandle<()>> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    let hangups = futures_util::stream::poll_fn(move |cx| hangup.poll_recv(cx))
+        .inspect(|_| tracing::info!("Received SIGHUP"));
+    Ok(tokio::spawn(reload_on(hangups, config, state)))
+}
+
+/// Reloads the config once per trigger, coalescing triggers that arrive
+/// within `reload_debounce_ms` of each other into a single reload.
+#[cfg(unix)]
+async fn reload_on(
+    triggers: impl futures_util::Stream<Item = ()>,
+    config: Arc<ArcSwap<ResolvedConfig>>,
+    state: Arc<RuntimeState>,
+) {
+    futures_util::pin_mut!(triggers);
+    while triggers.next().await.is_some() {
+        if let Some(debounce_ms) = config.load().reload_debounce_ms {
+            let debounce = Duration::from_millis(debounce_ms);
+            while let Ok(Some(())) = tokio::time::timeout(debounce, triggers.next()).await {}
+        }
+        tracing::info!("Reloading config");
+        // Failures are already logged, and the old config stays live
+        let _ = reload(&config, &state).await;
+    }
+}
+
+/// A successful output, with when it was run and the modification times its
+/// `cache_inputs` had then.
+struct CachedOutput {
+    cached_at: Instant,
+    stdout: Vec<u8>,
+    input_mtimes: Vec<Option<SystemTime>>,
+}
+
+/// Modification times of the output's `cache_inputs`, `None` for files that
+/// can't be read.
+fn input_mtimes(config: &ResolvedConfig, output_config: &OutputConfig) -> Vec<Option<SystemTime>> {
+    output_config
+        .cache_inputs
+        .iter()
+        .map(|path| {
+            std::fs::metadata(config.data_dir.join(path))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Mutable state shared by all requests, kept apart from the immutable config.
+#[derive(Default)]
+struct RuntimeState {
+    /// When each slug's command was last started, for `min_interval_secs`.
+    last_runs: Mutex<HashMap<String, Instant>>,
+    /// Successful outputs kept for `cache_ttl_secs` or `cache_inputs`.
+    output_cache: tokio::sync::RwLock<HashMap<String, CachedOutput>>,
+    /// Cache keys of expired outputs being refreshed for `async_refresh`.
+    refreshing: Mutex<HashSet<String>>,
+    /// Number of command executions currently running for each slug.
+    in_flight: Mutex<HashMap<String, usize>>,
+    /// Number of request bodies being buffered or piped, for `max_uploads`.
+    uploads: AtomicUsize,
+    /// Slots for `max_concurrent_commands`, sized when the server starts.
+    command_slots: Option<Arc<Semaphore>>,
+    jobs: JobStore,
+    http: reqwest::Client,
+    #[cfg(feature = "flags")]
+    flags: crate::flags::FlagClient,
+}
+
+impl RuntimeState {
+    fn new(config: &ResolvedConfig) -> Self {
+        Self {
+            command_slots: config
+                .max_concurrent_commands
+                .map(|max| Arc::new(Semaphore::new(max))),
+            ..Default::default()
+        }
+    }
+
+    /// Takes one of `max_concurrent_commands` command slots, waiting up to
+    /// `queue_timeout_secs` for one to free up unless the output sets
+    /// `reject_when_busy`.
+    async fn acquire_command_slot(
+        &self,
+        config: &ResolvedConfig,
+        output_config: &OutputConfig,
+    ) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(slots) = &self.command_slots else {
+            return Ok(None);
+        };
+        let busy = || {
+            poem::Error::from_string(
+                "Too many commands running",
+                poem::http::StatusCode::SERVICE_UNAVAILABLE,
+            )
+        };
+        if output_config.reject_when_busy {
+            return slots
+                .clone()
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| busy());
+        }
+        let acquire = slots.clone().acquire_owned();
+        let slot = match config.queue_timeout_secs {
+            Some(secs) => tokio::time::timeout(Duration::from_secs(secs), acquire)
+                .await
+                .map_err(|_| busy())?,
+            None => acquire.await,
+        };
+        Ok(Some(slot.expect("command slots are never closed")))
+    }
+
+    /// Marks `slug` as started now, unless it was already started less than
+    /// `min_interval` ago, in which case the remaining wait is returned.
+    fn try_start_run(&self, slug: &str, min_interval: Duration) -> Result<(), Duration> {
+        let mut last_runs = self.last_runs.lock().unwrap();
+        let now = Instant::now();
+        if let Some(last_run) = last_runs.get(slug) {
+            let elapsed = now.duration_since(*last_run);
+            if elapsed < min_interval {
+                return Err(min_interval - elapsed);
+            }
+        }
+        last_runs.insert(slug.to_string(), now);
+        Ok(())
+    }
+
+    /// Returns the cached output for `key` if it is younger than `ttl` and
+    /// was generated from inputs with the given modification times.
+    async fn cached_output(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        input_mtimes: &[Option<SystemTime>],
+    ) -> Option<Vec<u8>> {
+        let cache = self.output_cache.read().await;
+        let cached = cache.get(key)?;
+        let fresh = ttl.is_none_or(|ttl| cached.cached_at.elapsed() < ttl)
+            && cached.input_mtimes == input_mtimes;
+        fresh.then(|| cached.stdout.clone())
+    }
+
+    /// Returns the cached output for `key`, however old it is.
+    async fn stale_output(&self, key: &str) -> Option<Vec<u8>> {
+        let cache = self.output_cache.read().await;
+        cache.get(key).map(|cached| cached.stdout.clone())
+    }
+
+    /// Drops the cached outputs of every slug for which `keep` is false, and
+    /// returns how many were dropped.
+    async fn retain_cached_outputs(&self, keep: impl Fn(&str) -> bool) -> usize {
+        let mut cache = self.output_cache.write().await;
+        let before = cache.len();
+        cache.retain(|key, _| {
+            // Keys start with the slug, followed by the request's variables
+            let slug = key.split('\0').next().unwrap_or_default();
+            keep(slug)
+        });
+        before - cache.len()
+    }
+
+    /// Takes one of `max_uploads` upload slots until the guard is dropped, or
+    /// returns `None` if they are all taken.
+    fn try_start_upload(&self, max_uploads: Option<usize>) -> Option<UploadGuard<'_>> {
+        self.uploads
+            .fetch_update(
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                |uploads| match max_uploads {
+                    Some(max_uploads) if uploads >= max_uploads => None,
+                    _ => Some(uploads + 1),
+                },
+            )
+            .ok()?;
+        Some(UploadGuard { state: self })
+    }
+
+    /// Counts an execution of `slug` as in flight until the guard is dropped.
+    fn track_in_flight(self: &Arc<Self>, slug: &str) -> InFlightGuard {
+        *self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(slug.to_string())
+            .or_default() += 1;
+        InFlightGuard {
+            state: self.clone(),
+            slug: slug.to_string(),
+        }
+    }
+}
+
+/// Releases an upload slot taken with [`RuntimeState::try_start_upload`] when
+/// dropped.
+struct UploadGuard<'a> {
+    state: &'a RuntimeState,
+}
+
+impl Drop for UploadGuard<'_> {
+    fn drop(&mut self) {
+        self.state.uploads.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct InFlightGuard {
+    state: Arc<RuntimeState>,
+    slug: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = self.state.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.slug) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.slug);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    in_flight: InFlightStatus,
+    limits: Limits,
+}
+
+#[derive(Serialize)]
+struct InFlightStatus {
+    total: usize,
+    outputs: HashMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct Limits {
+    max_connections: Option<usize>,
+    max_uploads: Option<usize>,
+    max_concurrent_commands: Option<usize>,
+    queue_timeout_secs: Option<u64>,
+}
+
+/// Serves the API until SIGINT or SIGTERM is received. In-flight requests are
+/// then given up to `shutdown_timeout` to finish before they are dropped and
+/// their commands killed. Responses are compressed according to the client's
+/// `Accept-Encoding` if `compression` is set.
+pub async fn serve(
+    server_addrs: &[SocketAddr],
+    config: ResolvedConfig,
+    shutdown_timeout: Option<Duration>,
+    compression: bool,
+) -> Result<(), std::io::Error> {
+    let listener = bind_all(server_addrs).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to listen on")
+    })?;
+    let acceptor = ConnectionLimitListener::new(listener, config.max_connections)
+        .into_acceptor()
+        .await?;
+
+    for server_addr in server_addrs {
+        tracing::info!("Starting server at {}", server_addr);
+    }
+    serve_with_acceptor(
+        acceptor,
+        config,
+        shutdown_signal(),
+        shutdown_timeout,
+        compression,
+    )
+    .await
+}
+
+/// Listens on every address of `addrs`, or returns `None` if there is none.
+fn bind_all(addrs: &[SocketAddr]) -> Option<BoxListener> {
+    addrs
+        .iter()
+        .map(|addr| TcpListener::bind(*addr).boxed())
+        .reduce(|listener, next| listener.combine(next).boxed())
+}
+
+async fn serve_with_acceptor(
+    acceptor: impl Acceptor + 'static,
+    config: ResolvedConfig,
+    signal: impl Future<Output = ()>,
+    shutdown_timeout: Option<Duration>,
+    compression: bool,
+) -> Result<(), std::io::Error> {
+    let state = Arc::new(RuntimeState::new(&config));
+    let config = Arc::new(ArcSwap::from_pointee(config));
+
+    #[cfg(unix)]
+    let reloader = spawn_reload_on_hangup(config.clone(), state.clone())?;
+
+    let result = Server::new_with_acceptor(acceptor)
+        .run_with_graceful_shutdown(app(config, state, compression), signal, shutdown_timeout)
+        .await;
+
+    #[cfg(unix)]
+    reloader.abort();
+
+    result?;
+    tracing::info!("Server stopped");
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutdown signal received, draining in-flight requests");
+}
+
+/// A single JSON line describing the server, printed at startup for tools that
+/// parse logs.
+pub fn json_banner(server_addrs: &[SocketAddr], config: &ResolvedConfig) -> String {
+    let api_addr = server_addrs
+        .iter()
+        .map(SocketAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    serde_json::json!({
+        "version": crate::version(),
+        "api_addr": api_addr,
+        "data_dir": config.data_dir,
+        "outputs": config.outputs.len(),
+    })
+    .to_string()
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    git: &'static str,
+    build_timestamp: u64,
+    rustc: &'static str,
+}
+
+#[handler]
+fn get_version() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: crate::PKG_VERSION,
+        git: crate::GIT_VERSION,
+        build_timestamp: crate::BUILD_TIMESTAMP.parse().unwrap_or_default(),
+        rustc: crate::RUSTC_VERSION,
+    })
+}
+
+#[handler]
+async fn get_status(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+) -> Json<Status> {
+    let outputs = state.in_flight.lock().unwrap().clone();
+    Json(Status {
+        in_flight: InFlightStatus {
+            total: outputs.values().sum(),
+            outputs,
+        },
+        limits: Limits {
+            max_connections: config.max_connections,
+            max_uploads: config.max_uploads,
+            max_concurrent_commands: config.max_concurrent_commands,
+            queue_timeout_secs: config.queue_timeout_secs,
+        },
+    })
+}
+
+#[handler]
+async fn get_config(config: Data<&Arc<ResolvedConfig>>) -> Json<ResolvedConfig> {
+    Json(config.as_ref().clone())
 }
 
-pub async fn serve(server_addr: SocketAddr, config: ResolvedConfig) -> Result<(), std::io::Error> {
-    let app = app(config);
+#[derive(Serialize)]
+struct OutputSummary {
+    slug: String,
+}
+
+/// Lists the available outputs without exposing their commands.
+#[handler]
+async fn get_outputs(config: Data<&Arc<ResolvedConfig>>) -> Json<Vec<OutputSummary>> {
+    let mut slugs: Vec<_> = config.outputs.keys().cloned().collect();
+    slugs.sort();
+    Json(
+        slugs
+            .into_iter()
+            .map(|slug| OutputSummary { slug })
+            .collect(),
+    )
+}
+
+/// Plaintext summary of the endpoints and outputs, for humans. Only served
+/// if the current config sets `enable_help`.
+#[handler]
+async fn get_help(config: Data<&Arc<ResolvedConfig>>) -> Result<String> {
+    if !config.enable_help {
+        return Err(poem::Error::from_status(poem::http::StatusCode::NOT_FOUND));
+    }
+    let mut help = String::from(
+        "Endpoints:
+  GET  /output/<slug>        run an output and return its stdout
+  GET  /output/<slug>/<..>   run an output with its `path_params`
+  POST /output/<slug>        run an output with the request body as stdin
+  POST /output/<slug>/async  start an output in the background
+  GET  /jobs/<id>            result of a background run
+  GET  /outputs              list the outputs as JSON
+  GET  /config               the resolved config as JSON
+  GET  /status               commands currently running
+  GET  /version              build metadata as JSON
+  POST /rpc                  JSON-RPC 2.0 `run` method
+  POST /reload               re-read the config file (requires api_key)
+  POST /cache/invalidate     drop cached outputs (requires api_key)
+  GET  /help                 this text
+
+Outputs:
+",
+    );
+
+    let mut outputs: Vec<_> = config.outputs.values().collect();
+    outputs.sort_by(|a, b| a.slug.cmp(&b.slug));
+    for output in outputs {
+        match &output.description {
+            Some(description) => help.push_str(&format!("  {}  {}\n", output.slug, description)),
+            None => help.push_str(&format!("  {}\n", output.slug)),
+        }
+    }
+    Ok(help)
+}
+
+/// The parts of an output request that affect how the output is produced.
+#[derive(Debug, Default)]
+struct OutputRequest {
+    method: String,
+    path: String,
+    /// Every query parameter, in request order.
+    query: Vec<(String, String)>,
+    /// Request body passed to the command's stdin, for `accept_stdin`.
+    body: Option<Vec<u8>>,
+    pagination: Pagination,
+    /// `If-None-Match` header, answered with `304 Not Modified` when it
+    /// names the ETag of the response.
+    if_none_match: Option<String>,
+    /// Path segments after the slug, filling the `{name}` placeholders of
+    /// the output's `args` in order.
+    path_params: Vec<String>,
+}
+
+/// Query parameters read by the server itself, which are neither passed to
+/// commands nor part of the cache key.
+const RESERVED_QUERY_PARAMS: &[&str] = &["offset", "limit", "content_type"];
+
+/// Line range of an output to return, selected with `?offset=<n>&limit=<m>`.
+#[derive(Debug, Default, Deserialize)]
+struct Pagination {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/// The output request of a `GET`, with the path segments after the slug.
+fn get_request(
+    req: &Request,
+    query: Vec<(String, String)>,
+    pagination: Pagination,
+    path_params: Vec<String>,
+) -> OutputRequest {
+    OutputRequest {
+        method: req.method().to_string(),
+        path: req.uri().path().to_string(),
+        query,
+        body: None,
+        pagination,
+        if_none_match: req.header("If-None-Match").map(str::to_string),
+        path_params,
+    }
+}
+
+#[handler]
+async fn get_output(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+    Path(slug): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(query): Query<Vec<(String, String)>>,
+    req: &Request,
+) -> Result<Response> {
+    let request = get_request(req, query, pagination, Vec::new());
+    run_output_traced(&config, &state, &slug, &request).await
+}
+
+/// Runs an output with `path_params`, filled from the path segments after
+/// the slug.
+#[handler]
+async fn get_output_with_params(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+    Path((slug, params)): Path<(String, String)>,
+    Query(pagination): Query<Pagination>,
+    Query(query): Query<Vec<(String, String)>>,
+    req: &Request,
+) -> Result<Response> {
+    let params = params.split('/').map(str::to_string).collect();
+    let request = get_request(req, query, pagination, params);
+    run_output_traced(&config, &state, &slug, &request).await
+}
+
+/// `GET /output/<slug>/async` shares its route with starting a job, which
+/// is `POST` only, so `async` is passed on as a path parameter here.
+#[handler]
+async fn get_output_with_async_param(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+    Path(slug): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(query): Query<Vec<(String, String)>>,
+    req: &Request,
+) -> Result<Response> {
+    let request = get_request(req, query, pagination, vec!["async".to_string()]);
+    run_output_traced(&config, &state, &slug, &request).await
+}
+
+/// Runs an output with the request body as the command's stdin.
+#[handler]
+async fn post_output(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+    Path(slug): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(query): Query<Vec<(String, String)>>,
+    uri: &Uri,
+    body: Body,
+) -> Result<Response> {
+    let Some(output_config) = config
+        .get_output_by_slug(&slug)
+        .filter(|output| output.accept_stdin)
+    else {
+        return Err(poem::Error::from_status(
+            poem::http::StatusCode::METHOD_NOT_ALLOWED,
+        ));
+    };
+
+    // Take the slot before reading the body, which is buffered in memory
+    let Some(_upload) = state.try_start_upload(config.max_uploads) else {
+        return Err(poem::Error::from_string(
+            "Too many uploads in progress",
+            poem::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+    let body = match output_config.max_stdin_bytes {
+        Some(limit) => body.into_bytes_limit(limit).await?.to_vec(),
+        None => body.into_vec().await?,
+    };
+
+    let request = OutputRequest {
+        method: Method::POST.to_string(),
+        path: uri.path().to_string(),
+        query,
+        body: Some(body),
+        pagination,
+        if_none_match: None,
+        path_params: Vec::new(),
+    };
+    run_output_traced(&config, &state, &slug, &request).await
+}
+
+#[handler]
+async fn start_output_job(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+    Path(slug): Path<String>,
+    Query(query): Query<Vec<(String, String)>>,
+    method: Method,
+    uri: &Uri,
+) -> Result<Response> {
+    if config.get_output_by_slug(&slug).is_none() {
+        return Err(poem::Error::from_status(poem::http::StatusCode::NOT_FOUND));
+    }
+
+    let id = state.jobs.create(&slug);
+    tracing::info!("Started job {} for output {}", id, slug);
+
+    let config = config.clone();
+    let state = state.clone();
+    let job_id = id.clone();
+    let request = OutputRequest {
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        query,
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        let result = match run_output_traced(&config, &state, &slug, &request).await {
+            Ok(resp) => resp
+                .into_body()
+                .into_string()
+                .await
+                .map_err(|e| e.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        state.jobs.finish(&job_id, result);
+    });
+
+    Ok(Json(JobCreated { id: id.clone() })
+        .with_status(poem::http::StatusCode::ACCEPTED)
+        .with_header("Location", format!("/jobs/{id}"))
+        .into_response())
+}
+
+#[derive(Serialize)]
+struct JobCreated {
+    id: String,
+}
+
+/// JSON-RPC 2.0 request, e.g.
+/// `{"jsonrpc": "2.0", "method": "run", "params": {"slug": "report"}, "id": 1}`.
+#[derive(Deserialize)]
+struct RpcRequest {
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+    #[serde(default)]
+    id: serde_json::Value,
+}
+
+/// Parameters of the `run` method. Each of `args` is a `key=value` pair
+/// passed to the command like a query parameter.
+#[derive(Deserialize)]
+struct RunParams {
+    slug: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+}
+
+impl RpcError {
+    const PARSE_ERROR: i32 = -32700;
+    const METHOD_NOT_FOUND: i32 = -32601;
+    const INVALID_PARAMS: i32 = -32602;
+    /// The output failed; `data.status` holds the HTTP status it would have
+    /// been served with.
+    const OUTPUT_ERROR: i32 = -32000;
+
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+/// Runs outputs through JSON-RPC. Errors are reported in the response
+/// envelope, so the HTTP status is always `200 OK`.
+#[handler]
+async fn rpc(
+    config: Data<&Arc<ResolvedConfig>>,
+    state: Data<&Arc<RuntimeState>>,
+    body: String,
+) -> Json<RpcResponse> {
+    let (id, result) = match serde_json::from_str::<RpcRequest>(&body) {
+        Ok(request) => {
+            let result = run_rpc(&config, &state, request.method, request.params).await;
+            (request.id, result)
+        }
+        Err(e) => (
+            serde_json::Value::Null,
+            Err(RpcError::new(RpcError::PARSE_ERROR, e.to_string())),
+        ),
+    };
+
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    Json(RpcResponse {
+        jsonrpc: "2.0",
+        result,
+        error,
+        id,
+    })
+}
+
+async fn run_rpc(
+    config: &Arc<ResolvedConfig>,
+    state: &Arc<RuntimeState>,
+    method: String,
+    params: serde_json::Value,
+) -> std::result::Result<String, RpcError> {
+    if method != "run" {
+        return Err(RpcError::new(
+            RpcError::METHOD_NOT_FOUND,
+            format!("Unknown method: {method}"),
+        ));
+    }
+
+    let params: RunParams = serde_json::from_value(params)
+        .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e.to_string()))?;
+    let query = params
+        .args
+        .iter()
+        .map(|arg| {
+            arg.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| {
+                    RpcError::new(
+                        RpcError::INVALID_PARAMS,
+                        format!("Argument must be key=value: {arg}"),
+                    )
+                })
+        })
+        .collect::<std::result::Result<_, _>>()?;
+
+    let request = OutputRequest {
+        method: "POST".to_string(),
+        path: "/rpc".to_string(),
+        query,
+        ..Default::default()
+    };
+    let output_error = |err: poem::Error| RpcError {
+        code: RpcError::OUTPUT_ERROR,
+        message: err.to_string(),
+        data: Some(serde_json::json!({ "status": err.status().as_u16() })),
+    };
+    let resp = run_output_traced(config, state, &params.slug, &request)
+        .await
+        .map_err(output_error)?;
+    resp.into_body()
+        .into_string()
+        .await
+        .map_err(|e| output_error(e.into()))
+}
+
+#[handler]
+async fn get_job(state: Data<&Arc<RuntimeState>>, Path(id): Path<String>) -> Result<Json<Job>> {
+    state
+        .jobs
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| poem::Error::from_status(poem::http::StatusCode::NOT_FOUND))
+}
+
+async fn run_output_traced(
+    config: &Arc<ResolvedConfig>,
+    state: &Arc<RuntimeState>,
+    slug: &str,
+    request: &OutputRequest,
+) -> Result<Response> {
+    let span = tracing::info_span!(
+        "output",
+        slug = %slug,
+        status = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    let start = Instant::now();
+
+    let result = run_output(config, state, slug, request)
+        .instrument(span.clone())
+        .await;
+
+    let status = match &result {
+        Ok(resp) => resp.status(),
+        Err(err) => err.status(),
+    };
+    span.record("status", status.as_u16());
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+    span.in_scope(|| tracing::info!("Output request finished"));
+
+    result
+}
+
+/// Runs the output under a token shared by everything the request runs. It
+/// is cancelled once the request takes longer than `max_request_time_secs`,
+/// or is dropped because the client went away, which kills its commands.
+async fn run_output(
+    config: &Arc<ResolvedConfig>,
+    state: &Arc<RuntimeState>,
+    slug: &str,
+    request: &OutputRequest,
+) -> Result<Response> {
+    let cancel = CancellationToken::new();
+    let cancel_on_drop = cancel.clone().drop_guard();
+    let deadline = config.max_request_time_secs.map(|max_secs| {
+        (
+            max_secs,
+            tokio::time::Instant::now() + Duration::from_secs(max_secs),
+        )
+    });
+    let response = run_output_cancellable(config, state, slug, request, &cancel);
+    let result = match deadline {
+        Some((max_secs, deadline)) => {
+            tokio::pin!(response);
+            tokio::select! {
+                result = &mut response => result,
+                _ = tokio::time::sleep_until(deadline) => {
+                    cancel.cancel();
+                    // Lets a running command be killed and reaped
+                    let _ = response.await;
+                    tracing::error!("Output {} took longer than {}s", slug, max_secs);
+                    Err(poem::Error::from_string(
+                        format!("request took longer than {max_secs} seconds"),
+                        poem::http::StatusCode::GATEWAY_TIMEOUT,
+                    ))
+                }
+            }
+        }
+        None => response.await,
+    };
+    // A streamed body outlives this function and cancels the token itself
+    if result.is_ok()
+        && config
+            .get_output_by_slug(slug)
+            .is_some_and(|output| output.stream)
+    {
+        let cancel = cancel_on_drop.disarm();
+        if let Some((_, deadline)) = deadline {
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => cancel.cancel(),
+                    _ = cancel.cancelled() => {}
+                }
+            });
+        }
+    }
+    result
+}
+
+async fn run_output_cancellable(
+    config: &Arc<ResolvedConfig>,
+    state: &Arc<RuntimeState>,
+    slug: &str,
+    request: &OutputRequest,
+    cancel: &CancellationToken,
+) -> Result<Response> {
+    let output_config = config
+        .get_output_by_slug(slug)
+        .ok_or_else(|| poem::Error::from_status(poem::http::StatusCode::NOT_FOUND))?;
+
+    if output_config.path_params.len() != request.path_params.len() {
+        return Err(poem::Error::from_status(poem::http::StatusCode::NOT_FOUND));
+    }
+    let substituted;
+    let output_config = if output_config.path_params.is_empty() {
+        output_config
+    } else {
+        let params: Vec<_> = output_config
+            .path_params
+            .iter()
+            .cloned()
+            .zip(request.path_params.clone())
+            .collect();
+        // Values end up as command arguments, so only plain words are allowed
+        if let Some((name, value)) = params.iter().find(|(_, value)| !is_valid_path_param(value)) {
+            return Err(poem::Error::from_string(
+                format!("Invalid path parameter {name}: {value}"),
+                poem::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+        let mut output_config = output_config.clone();
+        output_config.substitute_path_params(&params);
+        substituted = output_config;
+        &substituted
+    };
+
+    if let Some(delay_ms) = output_config.artificial_delay_ms {
+        tracing::info!(
+            "Delaying output {} by {}ms (artificial_delay_ms)",
+            slug,
+            delay_ms
+        );
+        cancel
+            .run_until_cancelled(tokio::time::sleep(Duration::from_millis(delay_ms)))
+            .await
+            .ok_or_else(request_cancelled)?;
+    }
+
+    #[cfg(feature = "flags")]
+    if let (Some(flag), Some(flags_url)) = (&output_config.enabled_flag, &config.flags_url) {
+        let cache_ttl = config
+            .flags_cache_secs
+            .map_or(crate::flags::DEFAULT_FLAG_CACHE_TTL, Duration::from_secs);
+        if !state.flags.is_enabled(flags_url, flag, cache_ttl).await {
+            tracing::debug!("Flag {} is off, hiding output {}", flag, slug);
+            return Err(poem::Error::from_status(poem::http::StatusCode::NOT_FOUND));
+        }
+    }
+
+    let env = command_env(output_config, request)?;
+    let requested_content_type = request
+        .query
+        .iter()
+        .find(|(key, _)| key == "content_type")
+        .map(|(_, value)| value.as_str())
+        .filter(|_| config.allow_debug);
+    if let Some(content_type) = requested_content_type {
+        if !is_valid_mime(content_type) {
+            return Err(poem::Error::from_string(
+                format!("Invalid content_type: {content_type}"),
+                poem::http::StatusCode::BAD_REQUEST,
+            ));
+        }
+    }
+    let content_type = requested_content_type
+        .or(output_config.content_type.as_deref())
+        .unwrap_or("text/plain; charset=utf-8");
+
+    // Outputs taking request parameters are cached per set of parameters
+    let cache_key = std::iter::once(slug.to_string())
+        .chain(request.path_params.iter().cloned())
+        .chain(env.iter().map(|(key, value)| format!("{key}={value}")))
+        .collect::<Vec<_>>()
+        .join("\0");
+    // Request bodies are not part of the key, so requests with one bypass it
+    let cacheable = (output_config.cache_ttl_secs.is_some()
+        || !output_config.cache_inputs.is_empty())
+        && request.body.is_none();
+    let cache_ttl = output_config.cache_ttl_secs.map(Duration::from_secs);
+    let mut stale = false;
+    let cached = if cacheable {
+        let input_mtimes = input_mtimes(config, output_config);
+        match state
+            .cached_output(&cache_key, cache_ttl, &input_mtimes)
+            .await
+        {
+            None if output_config.async_refresh => {
+                let stale_output = state.stale_output(&cache_key).await;
+                if stale_output.is_some() {
+                    stale = true;
+                    spawn_refresh(config, state, output_config, &env, &cache_key);
+                }
+                stale_output
+            }
+            cached => cached,
+        }
+    } else {
+        None
+    };
+
+    let stdout = match cached {
+        Some(stdout) => {
+            tracing::debug!("Serving output {} from cache", slug);
+            stdout
+        }
+        None => {
+            if let Some(min_interval_secs) = output_config.min_interval_secs {
+                if let Err(remaining) =
+                    state.try_start_run(slug, Duration::from_secs(min_interval_secs))
+                {
+                    let retry_after = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+                    return Err(poem::Error::from_response(
+                        Response::builder()
+                            .status(poem::http::StatusCode::TOO_MANY_REQUESTS)
+                            .header("Retry-After", retry_after)
+                            .body(format!(
+                                "Output {slug} can only run once every {min_interval_secs} seconds"
+                            )),
+                    ));
+                }
+            }
+
+            if let Some(proxy_url) = &output_config.proxy_url {
+                return cancel
+                    .run_until_cancelled(proxy_output(&state.http, proxy_url))
+                    .await
+                    .ok_or_else(request_cancelled)?;
+            }
+
+            let stdin = match (&request.body, &output_config.stdin_url) {
+                (Some(body), _) => Some(body.clone()),
+                (None, Some(url)) => Some(
+                    cancel
+                        .run_until_cancelled(fetch_stdin(&state.http, url))
+                        .await
+                        .ok_or_else(request_cancelled)??,
+                ),
+                (None, None) => None,
+            };
+
+            if output_config.stream {
+                return stream_command(
+                    config,
+                    state,
+                    output_config,
+                    &env,
+                    stdin.as_deref(),
+                    content_type,
+                    cancel,
+                )
+                .await;
+            }
+
+            let cache_key = cacheable.then_some(cache_key);
+            run_and_cache(
+                config,
+                state,
+                output_config,
+                &env,
+                stdin.as_deref(),
+                cache_key,
+                cancel,
+            )
+            .await?
+        }
+    };
+
+    if stdout.is_empty() {
+        match output_config.empty_output {
+            EmptyOutput::Ok => {}
+            EmptyOutput::NoContent => {
+                return Ok(Response::builder()
+                    .status(poem::http::StatusCode::NO_CONTENT)
+                    .finish());
+            }
+            EmptyOutput::Error => {
+                return Err(poem::Error::from_string(
+                    format!("Command for output {slug} produced no output"),
+                    poem::http::StatusCode::BAD_GATEWAY,
+                ));
+            }
+        }
+    }
+
+    let stdout = match output_config.minify {
+        _ if stdout.is_empty() => stdout,
+        Minify::None => stdout,
+        minify => minify_output(minify, &stdout).map_err(|e| {
+            tracing::error!("Failed to minify output {}: {}", slug, e);
+            poem::Error::from_string(
+                format!("Output {slug} could not be minified: {e}"),
+                poem::http::StatusCode::BAD_GATEWAY,
+            )
+        })?,
+    };
+    let stdout = normalize_line_endings(output_config.line_endings, stdout);
+
+    let content = String::from_utf8(stdout.clone())
+        .unwrap_or_else(|_| String::from_utf8_lossy(&stdout).to_string());
+
+    let pagination = &request.pagination;
+    let (body, total_lines) = if pagination.offset.is_none() && pagination.limit.is_none() {
+        (content, None)
+    } else {
+        let (page, total_lines) = paginate_lines(&content, pagination);
+        (page, Some(total_lines))
+    };
+
+    let etag = etag(body.as_bytes());
+    if request
+        .if_none_match
+        .as_deref()
+        .is_some_and(|if_none_match| etag_matches(if_none_match, &etag))
+    {
+        return Ok(Response::builder()
+            .status(poem::http::StatusCode::NOT_MODIFIED)
+            .header("ETag", etag)
+            .finish());
+    }
+
+    let mut resp = Response::builder()
+        .header("Content-Type", content_type)
+        .header("ETag", etag);
+    if stale {
+        resp = resp
+            .status(poem::http::StatusCode::ACCEPTED)
+            .header("X-Cache", "stale");
+    }
+    if config.allow_debug {
+        let working_dir = config.command_dir(output_config).display().to_string();
+        resp = resp.header("X-Working-Dir", working_dir);
+    }
+    if let Some(total_lines) = total_lines {
+        resp = resp.header("X-Total-Lines", total_lines);
+    }
+    Ok(resp.body(body))
+}
+
+/// Runs the output's commands, notifies its webhook, and caches the output
+/// under `cache_key` if given.
+async fn run_and_cache(
+    config: &ResolvedConfig,
+    state: &Arc<RuntimeState>,
+    output_config: &OutputConfig,
+    env: &[(String, String)],
+    stdin: Option<&[u8]>,
+    cache_key: Option<String>,
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>> {
+    let slug = &output_config.slug;
+    // Taken before running, so inputs changed while it runs cause a rerun
+    let input_mtimes = input_mtimes(config, output_config);
+    let stdout = run_commands(config, state, output_config, slug, env, stdin, cancel).await?;
+    if let Some(webhook_url) = &output_config.webhook_url {
+        tokio::spawn(send_webhook(
+            state.http.clone(),
+            webhook_url.clone(),
+            slug.clone(),
+            stdout.clone(),
+        ));
+    }
+    if let Some(cache_key) = cache_key {
+        state
+            .output_cache
+            .write()
+            .await
+            .insert(cache_key, CachedOutput {
+                cached_at: Instant::now(),
+                stdout: stdout.clone(),
+                input_mtimes,
+            });
+    }
+    Ok(stdout)
+}
+
+/// Refreshes the expired cached output under `cache_key` in the background,
+/// unless a refresh of it is already running or `min_interval_secs` doesn't
+/// allow another run yet.
+fn spawn_refresh(
+    config: &Arc<ResolvedConfig>,
+    state: &Arc<RuntimeState>,
+    output_config: &OutputConfig,
+    env: &[(String, String)],
+    cache_key: &str,
+) {
+    if !state
+        .refreshing
+        .lock()
+        .unwrap()
+        .insert(cache_key.to_string())
+    {
+        return;
+    }
+    if let Some(min_interval_secs) = output_config.min_interval_secs {
+        if state
+            .try_start_run(&output_config.slug, Duration::from_secs(min_interval_secs))
+            .is_err()
+        {
+            tracing::debug!(
+                "Not refreshing output {} yet (min_interval_secs)",
+                output_config.slug
+            );
+            state.refreshing.lock().unwrap().remove(cache_key);
+            return;
+        }
+    }
+    let config = config.clone();
+    let state = state.clone();
+    let output_config = output_config.clone();
+    let env = env.to_vec();
+    let cache_key = cache_key.to_string();
+    tokio::spawn(
+        async move {
+            let result = async {
+                let stdin = match &output_config.stdin_url {
+                    Some(url) => Some(fetch_stdin(&state.http, url).await?),
+                    None => None,
+                };
+                // Outlives the request, so it is never cancelled
+                let cancel = CancellationToken::new();
+                let cache_key = Some(cache_key.clone());
+                run_and_cache(
+                    &config,
+                    &state,
+                    &output_config,
+                    &env,
+                    stdin.as_deref(),
+                    cache_key,
+                    &cancel,
+                )
+                .await
+            }
+            .await;
+            if let Err(e) = result {
+                tracing::error!("Failed to refresh output {}: {}", output_config.slug, e);
+            }
+            state.refreshing.lock().unwrap().remove(&cache_key);
+        }
+        .in_current_span(),
+    );
+}
+
+/// Strong ETag of a response body: the quoted SHA-256 of its bytes.
+fn etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+/// Whether an `If-None-Match` header value names `etag`, comparing weakly as
+/// RFC 9110 requires for this header.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Minifies `stdout` as JSON or HTML. HTML only has to be valid UTF-8, since
+/// the minifier accepts any markup.
+fn minify_output(minify: Minify, stdout: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    match minify {
+        Minify::None => Ok(stdout.to_vec()),
+        Minify::Json => {
+            let value: serde_json::Value =
+                serde_json::from_slice(stdout).map_err(|e| format!("invalid JSON: {e}"))?;
+            serde_json::to_vec(&value).map_err(|e| e.to_string())
+        }
+        Minify::Html => {
+            std::str::from_utf8(stdout).map_err(|e| format!("invalid HTML: {e}"))?;
+            Ok(minify_html::minify(stdout, &minify_html::Cfg::new()))
+        }
+    }
+}
+
+/// Converts every line ending in `stdout` to the chosen style.
+fn normalize_line_endings(line_endings: LineEndings, stdout: Vec<u8>) -> Vec<u8> {
+    if line_endings == LineEndings::Preserve {
+        return stdout;
+    }
+    let mut normalized = Vec::with_capacity(stdout.len());
+    let mut bytes = stdout.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue;
+        }
+        if byte == b'\n' && line_endings == LineEndings::Crlf {
+            normalized.push(b'\r');
+        }
+        normalized.push(byte);
+    }
+    normalized
+}
+
+/// Runs the output's command, then its fallbacks until one succeeds, and
+/// returns its stdout.
+async fn run_commands(
+    config: &ResolvedConfig,
+    state: &Arc<RuntimeState>,
+    output_config: &OutputConfig,
+    slug: &str,
+    env: &[(String, String)],
+    stdin: Option<&[u8]>,
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>> {
+    let mut commands = vec![output_config.get_command_parts()];
+    commands.extend(
+        output_config
+            .fallbacks
+            .iter()
+            .map(|fallback| (fallback.cmd.clone(), fallback.args.clone())),
+    );
+
+    let _slot = cancel
+        .run_until_cancelled(state.acquire_command_slot(config, output_config))
+        .await
+        .ok_or_else(request_cancelled)??;
+    let in_flight = state.track_in_flight(slug);
+    let mut errors = Vec::new();
+    let mut stdout = None;
+    for (cmd, args) in commands {
+        // A timeout only cancels this attempt, so the fallbacks still run
+        let attempt = cancel.child_token();
+        let execution = execute_command(config, output_config, &cmd, args, env, stdin, &attempt);
+        let result = match output_config.timeout_secs {
+            Some(timeout_secs) => {
+                tokio::pin!(execution);
+                tokio::select! {
+                    result = &mut execution => result,
+                    _ = tokio::time::sleep(Duration::from_secs(timeout_secs)) => {
+                        attempt.cancel();
+                        execution.await.map_err(|_| {
+                            tracing::error!("Command {} timed out after {}s", cmd, timeout_secs);
+                            poem::Error::from_string(
+                                format!("command timed out after {timeout_secs} seconds"),
+                                poem::http::StatusCode::GATEWAY_TIMEOUT,
+                            )
+                        })
+                    }
+                }
+            }
+            None => execution.await,
+        };
+        match result {
+            Ok(output) => {
+                stdout = Some(output);
+                break;
+            }
+            // The request is gone, so there is no point in trying fallbacks
+            Err(err) if cancel.is_cancelled() => {
+                errors.push((cmd, err));
+                break;
+            }
+            Err(err) => {
+                if !output_config.fallbacks.is_empty() {
+                    tracing::warn!("Command {} failed, trying next fallback", cmd);
+                }
+                errors.push((cmd, err));
+            }
+        }
+    }
+
+    drop(in_flight);
+
+    if let Some(stdout) = stdout {
+        return Ok(stdout);
+    }
+    Err(match errors.len() {
+        1 => errors.remove(0).1,
+        _ => poem::Error::from_string(
+            errors
+                .iter()
+                .map(|(cmd, err)| format!("{cmd}: {err}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    })
+}
+
+/// Returns the selected lines, keeping their line endings, and the total number
+/// of lines in `content`.
+fn paginate_lines(content: &str, pagination: &Pagination) -> (String, usize) {
+    let total_lines = content.split_inclusive('\n').count();
+    let page = content
+        .split_inclusive('\n')
+        .skip(pagination.offset.unwrap_or(0))
+        .take(pagination.limit.unwrap_or(usize::MAX))
+        .collect();
+    (page, total_lines)
+}
+
+/// Extra environment variables for the output's command, derived from the
+/// request.
+fn command_env(
+    output_config: &OutputConfig,
+    request: &OutputRequest,
+) -> Result<Vec<(String, String)>> {
+    let mut env = Vec::new();
+    if output_config.pass_request_meta {
+        env.push((
+            "JUNCTION_REQUEST_METHOD".to_string(),
+            request.method.clone(),
+        ));
+        env.push(("JUNCTION_REQUEST_PATH".to_string(), request.path.clone()));
+    }
+
+    if output_config.allow_query_env {
+        let allowed = &output_config.allowed_query_params;
+        for (key, value) in &request.query {
+            if RESERVED_QUERY_PARAMS.contains(&key.as_str()) {
+                continue;
+            }
+            if !allowed.is_empty() && !allowed.contains(key) {
+                tracing::debug!("Ignoring query parameter {} not in the allow-list", key);
+                continue;
+            }
+            // Only plain names, so a parameter can't set arbitrary variables
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(poem::Error::from_string(
+                    format!("Invalid query parameter name: {key}"),
+                    poem::http::StatusCode::BAD_REQUEST,
+                ));
+            }
+            env.push((format!("JUNCTION_ARG_{key}"), value.clone()));
+        }
+    }
+
+    Ok(env)
+}
+
+/// Relays the response of `url`, keeping its status and `Content-Type`.
+async fn proxy_output(client: &reqwest::Client, url: &str) -> Result<Response> {
+    let fetch = async {
+        let response = client.get(url).send().await?;
+        let status = response.status().as_u16();
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .cloned();
+        let body = response.bytes().await?;
+        Ok::<_, reqwest::Error>((status, content_type, body))
+    };
+
+    let (status, content_type, body) = fetch.await.map_err(|e| {
+        tracing::error!("Failed to proxy {}: {}", url, e);
+        poem::Error::from_string(
+            format!("Failed to proxy {url}: {e}"),
+            poem::http::StatusCode::BAD_GATEWAY,
+        )
+    })?;
+
+    let mut response = Response::builder().status(
+        poem::http::StatusCode::from_u16(status).unwrap_or(poem::http::StatusCode::BAD_GATEWAY),
+    );
+    if let Some(content_type) = content_type {
+        response = response.header("Content-Type", content_type.as_bytes());
+    }
+    Ok(response.body(body.to_vec()))
+}
+
+async fn fetch_stdin(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let fetch = async {
+        let response = client.get(url).send().await?.error_for_status()?;
+        response.bytes().await
+    };
+
+    let body = fetch.await.map_err(|e| {
+        tracing::error!("Failed to fetch stdin from {}: {}", url, e);
+        poem::Error::from_string(
+            format!("Failed to fetch stdin from {url}: {e}"),
+            poem::http::StatusCode::BAD_GATEWAY,
+        )
+    })?;
+    Ok(body.to_vec())
+}
+
+/// POSTs the output of a run of `slug` to `url`, logging failures.
+async fn send_webhook(client: reqwest::Client, url: String, slug: String, stdout: Vec<u8>) {
+    let result = client
+        .post(&url)
+        .header("X-Junction-Slug", &slug)
+        .body(stdout)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    match result {
+        Ok(_) => tracing::debug!("Sent output {} to webhook {}", slug, url),
+        Err(e) => tracing::warn!("Failed to send output {} to webhook {}: {}", slug, url, e),
+    }
+}
+
+async fn execute_command(
+    config: &ResolvedConfig,
+    output_config: &OutputConfig,
+    cmd: &str,
+    args: Vec<String>,
+    env: &[(String, String)],
+    stdin: Option<&[u8]>,
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>> {
+    if output_config.pty {
+        return execute_in_pty(config, output_config, cmd, args, env, cancel).await;
+    }
+
+    let mut child = spawn_command(config, output_config, cmd, args, env, stdin).await?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let wait = async {
+        let mut stdout_buf = Vec::new();
+        let mut stderr_buf = Vec::new();
+        let (status, _, _) = tokio::try_join!(
+            child.wait(),
+            stdout.read_to_end(&mut stdout_buf),
+            stderr.read_to_end(&mut stderr_buf),
+        )?;
+        Ok(std::process::Output {
+            status,
+            stdout: stdout_buf,
+            stderr: stderr_buf,
+        })
+    };
+
+    let output = tokio::select! {
+        output = wait => output.map_err(spawn_error)?,
+        _ = cancel.cancelled() => {
+            if let Err(e) = child.kill().await {
+                tracing::warn!("Failed to kill cancelled command {}: {}", cmd, e);
+            }
+            return Err(cancelled_error(cmd));
+        }
+    };
+
+    // Log stderr to server logs, always for failures and sampled otherwise
+    if !output.stderr.is_empty() {
+        let stderr_str = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            if sample_log(output_config.log_sample_rate, &mut rand::rng()) {
+                tracing::info!("Command stderr output:\n{}", stderr_str);
+            }
+        } else {
+            tracing::error!(
+                "Command failed with status: {}. Stderr:\n{}",
+                output.status,
+                stderr_str
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&output.status) {
+        let name = signal_name(signal).map(|name| format!(" ({name})"));
+        return Err(poem::Error::from_string(
+            format!(
+                "Command for output {} was killed by signal {}{}",
+                output_config.slug,
+                signal,
+                name.unwrap_or_default()
+            ),
+            poem::http::StatusCode::BAD_GATEWAY,
+        ));
+    }
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(poem::Error::from_string(
+            command_error_body(config, &output_config.slug, output.status.code(), &stderr),
+            poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Name of the common signals that end a command.
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        _ => return None,
+    })
+}
+
+/// Creates the output's `working_dir` if it sets `create_working_dir`.
+fn create_working_dir(output_config: &OutputConfig) -> Result<()> {
+    let Some(working_dir) = output_config
+        .working_dir
+        .as_ref()
+        .filter(|_| output_config.create_working_dir)
+    else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(working_dir).map_err(|e| {
+        tracing::error!(
+            "Failed to create working directory {}: {}",
+            working_dir.display(),
+            e
+        );
+        poem::Error::from_string(
+            format!("failed to create working directory: {e}"),
+            poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    })
+}
+
+/// Starts `cmd` in the command directory with stdout and stderr piped, and
+/// feeds it `stdin` in the background. The child is killed when dropped.
+async fn spawn_command(
+    config: &ResolvedConfig,
+    output_config: &OutputConfig,
+    cmd: &str,
+    args: Vec<String>,
+    env: &[(String, String)],
+    stdin: Option<&[u8]>,
+) -> Result<Child> {
+    create_working_dir(output_config)?;
+    let mut command = Command::new(cmd);
+    for name in &config.strip_env {
+        command.env_remove(name);
+    }
+    command
+        .args(args)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .current_dir(config.command_dir(output_config));
+
+    if let Some(path) = command_path(config) {
+        command.env("PATH", path);
+    }
+    command.envs(&output_config.env);
+
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut child = retry_spawn(|| command.spawn()).await.map_err(spawn_error)?;
+
+    // Feed stdin concurrently so a child filling its stdout can't deadlock us
+    if let (Some(stdin), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+        let stdin = stdin.to_vec();
+        tokio::spawn(async move {
+            if let Err(e) = child_stdin.write_all(&stdin).await {
+                tracing::warn!("Failed to write command stdin: {}", e);
+            }
+        });
+    }
+
+    Ok(child)
+}
+
+/// Number of times a command is spawned before a transient failure is
+/// returned.
+const SPAWN_ATTEMPTS: u32 = 3;
+/// Wait before the first spawn retry, doubled for every further retry.
+const SPAWN_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Calls `spawn` until it succeeds, fails with a non-transient error or runs
+/// out of attempts. Forking fails with `EAGAIN` when the system is
+/// temporarily out of processes or memory, which is worth waiting out.
+async fn retry_spawn<T>(mut spawn: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match spawn() {
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && attempt < SPAWN_ATTEMPTS => {
+                let backoff = SPAWN_RETRY_BACKOFF * 2u32.pow(attempt - 1);
+                tracing::warn!("Failed to spawn command: {}, retrying in {:?}", e, backoff);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn spawn_error(e: std::io::Error) -> poem::Error {
+    poem::Error::from_string(
+        format!("Failed to execute command: {e}"),
+        poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+    )
+}
+
+/// Runs the output's command and streams its stdout as the response body.
+/// Failures after the response has started are logged and signalled to the
+/// client per the output's `stream_error_mode`.
+async fn stream_command(
+    config: &ResolvedConfig,
+    state: &Arc<RuntimeState>,
+    output_config: &OutputConfig,
+    env: &[(String, String)],
+    stdin: Option<&[u8]>,
+    content_type: &str,
+    cancel: &CancellationToken,
+) -> Result<Response> {
+    let (cmd, args) = output_config.get_command_parts();
+    let slot = cancel
+        .run_until_cancelled(state.acquire_command_slot(config, output_config))
+        .await
+        .ok_or_else(request_cancelled)??;
+    let mut child = spawn_command(config, output_config, &cmd, args, env, stdin).await?;
+    let in_flight = state.track_in_flight(&output_config.slug);
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+
+    // The guard goes with the response body, so the request is cancelled
+    // once the client went away
+    let cancel_on_drop = cancel.clone().drop_guard();
+    let cancel = cancel.clone();
+    let (exit_code_tx, exit_code_rx) = tokio::sync::oneshot::channel();
+    let slug = output_config.slug.clone();
+    let timeout_secs = output_config.timeout_secs;
+    tokio::spawn(async move {
+        let mut stderr_buf = Vec::new();
+        let finished = async {
+            if let Err(e) = stderr.read_to_end(&mut stderr_buf).await {
+                tracing::warn!("Failed to read stderr of output {}: {}", slug, e);
+            }
+            child.wait().await
+        };
+        let deadline = async {
+            match timeout_secs {
+                Some(timeout_secs) => tokio::time::sleep(Duration::from_secs(timeout_secs)).await,
+                None => std::future::pending().await,
+            }
+        };
+        let status = tokio::select! {
+            status = finished => Some(status),
+            _ = deadline => {
+                tracing::error!(
+                    "Streamed command for output {} timed out after {}s",
+                    slug,
+                    timeout_secs.unwrap_or_default()
+                );
+                None
+            }
+            _ = cancel.cancelled() => {
+                tracing::warn!("Streamed output {} was cancelled, killing the command", slug);
+                None
+            }
+        };
+        let Some(status) = status else {
+            let _ = child.kill().await;
+            drop(in_flight);
+            drop(slot);
+            let _ = exit_code_tx.send(None);
+            return;
+        };
+        let stderr = String::from_utf8_lossy(&stderr_buf);
+        drop(in_flight);
+        drop(slot);
+        let _ = exit_code_tx.send(status.as_ref().ok().and_then(|status| status.code()));
+        match status {
+            Ok(status) if status.success() => {
+                if !stderr.is_empty() {
+                    tracing::info!("Command stderr output:\n{}", stderr);
+                }
+            }
+            Ok(status) => tracing::error!(
+                "Streamed command for output {} failed with status: {}. Stderr:\n{}",
+                slug,
+                status,
+                stderr
+            ),
+            Err(e) => tracing::error!("Failed to wait for streamed output {}: {}", slug, e),
+        }
+    });
+
+    let data = ReaderStream::new(stdout).map_ok(Frame::data);
+    let error_mode = output_config.stream_error_mode;
+    let end = futures_util::stream::once(async move {
+        let _cancel_on_drop = cancel_on_drop;
+        let code = exit_code_rx.await.ok().flatten();
+        if error_mode == StreamErrorMode::Event && code != Some(0) {
+            let reason = match code {
+                Some(code) => format!("command exited with code {code}"),
+                None => "command was killed".to_string(),
+            };
+            return Ok(Frame::data(
+                format!("event: error\ndata: {reason}\n\n").into(),
+            ));
+        }
+        let mut trailers = poem::http::HeaderMap::new();
+        if let Some(code) = code {
+            trailers.insert("X-Exit-Code", code.into());
+        }
+        Ok(Frame::trailers(trailers))
+    });
+    let body = BoxBody::new(StreamBody::new(data.chain(end)));
+
+    Ok(Response::builder()
+        .header("Content-Type", content_type)
+        .header("Trailer", "X-Exit-Code")
+        .body(Body::from(body)))
+}
+
+/// Runs the command attached to a pseudo-terminal, so it behaves as if run
+/// interactively. Stdout and stderr are combined in the returned output.
+#[cfg(unix)]
+async fn execute_in_pty(
+    config: &ResolvedConfig,
+    output_config: &OutputConfig,
+    cmd: &str,
+    args: Vec<String>,
+    env: &[(String, String)],
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    use portable_pty::CommandBuilder;
+    use portable_pty::PtySize;
+
+    create_working_dir(output_config)?;
+    let mut command = CommandBuilder::new(cmd);
+    for name in &config.strip_env {
+        command.env_remove(name);
+    }
+    command.args(args);
+    command.cwd(config.command_dir(output_config));
+    for (key, value) in env {
+        command.env(key, value);
+    }
+    if let Some(path) = command_path(config) {
+        command.env("PATH", path);
+    }
+    for (key, value) in &output_config.env {
+        command.env(key, value);
+    }
+
+    let pty_error =
+        |e: String| poem::Error::from_string(e, poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    let pair = portable_pty::native_pty_system()
+        .openpty(PtySize::default())
+        .map_err(|e| pty_error(format!("Failed to open pty: {e}")))?;
+    let mut child = pair
+        .slave
+        .spawn_command(command)
+        .map_err(|e| pty_error(format!("Failed to execute command: {e}")))?;
+    // Only the child may hold the terminal open, so reading ends with it
+    drop(pair.slave);
+    let mut killer = PtyKillOnDrop(Some(child.clone_killer()));
+
+    let execute = move || -> std::result::Result<(portable_pty::ExitStatus, Vec<u8>), String> {
+        let mut reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| format!("Failed to read pty: {e}"))?;
+        let mut output = Vec::new();
+        let mut buf = [0; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                // Linux reports EIO once the child side of the pty is closed
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                Err(e) => return Err(format!("Failed to read pty: {e}")),
+            }
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| format!("Failed to wait for command: {e}"))?;
+        Ok((status, output))
+    };
+
+    let mut execution = tokio::task::spawn_blocking(execute);
+    let result = tokio::select! {
+        result = &mut execution => result,
+        _ = cancel.cancelled() => {
+            drop(killer);
+            // Reading stops once the child is gone; wait so it is reaped
+            let _ = execution.await;
+            return Err(cancelled_error(cmd));
+        }
+    };
+    killer.0.take();
+    let (status, output) = result
+        .map_err(|e| e.to_string())
+        .and_then(|result| result)
+        .map_err(pty_error)?;
+
+    if !status.success() {
+        let output = String::from_utf8_lossy(&output);
+        tracing::error!(
+            "Command failed with status: {}. Output:\n{}",
+            status,
+            output
+        );
+        let exit_code = i32::try_from(status.exit_code()).ok();
+        return Err(poem::Error::from_string(
+            command_error_body(config, &output_config.slug, exit_code, &output),
+            poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ));
+    }
+
+    Ok(output)
+}
+
+/// Kills a pty child when dropped, unless it was disarmed after the child
+/// exited.
+#[cfg(unix)]
+struct PtyKillOnDrop(Option<Box<dyn portable_pty::ChildKiller + Send + Sync>>);
+
+#[cfg(unix)]
+impl Drop for PtyKillOnDrop {
+    fn drop(&mut self) {
+        if let Some(mut killer) = self.0.take() {
+            if let Err(e) = killer.kill() {
+                tracing::warn!("Failed to kill pty command: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn execute_in_pty(
+    _: &ResolvedConfig,
+    _: &OutputConfig,
+    _: &str,
+    _: Vec<String>,
+    _: &[(String, String)],
+    _: &CancellationToken,
+) -> Result<Vec<u8>> {
+    Err(poem::Error::from_string(
+        "Running commands in a pty is only supported on Unix",
+        poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+    ))
+}
+
+/// Whether to log a successful run's stderr, given the output's
+/// `log_sample_rate`. Without a rate, everything is logged.
+fn sample_log(rate: Option<f64>, rng: &mut impl rand::Rng) -> bool {
+    rate.is_none_or(|rate| rng.random_bool(rate))
+}
+
+fn request_cancelled() -> poem::Error {
+    poem::Error::from_string(
+        "Request was cancelled",
+        poem::http::StatusCode::SERVICE_UNAVAILABLE,
+    )
+}
+
+fn cancelled_error(cmd: &str) -> poem::Error {
+    poem::Error::from_string(
+        format!("Command {cmd} was cancelled"),
+        poem::http::StatusCode::INTERNAL_SERVER_ERROR,
+    )
+}
+
+/// The body returned for a failed command: the configured `error_template`
+/// rendered with the failure details, or the raw stderr otherwise.
+fn command_error_body(
+    config: &ResolvedConfig,
+    slug: &str,
+    exit_code: Option<i32>,
+    stderr: &str,
+) -> String {
+    let Some(path) = &config.error_template else {
+        return stderr.to_string();
+    };
+
+    match std::fs::read_to_string(path) {
+        Ok(template) => render_error_template(&template, slug, exit_code, stderr),
+        Err(e) => {
+            tracing::warn!("Failed to read error template {}: {}", path.display(), e);
+            stderr.to_string()
+        }
+    }
+}
+
+fn render_error_template(
+    template: &str,
+    slug: &str,
+    exit_code: Option<i32>,
+    stderr: &str,
+) -> String {
+    let exit_code = exit_code.map(|code| code.to_string()).unwrap_or_default();
+    // Substitute stderr last so tokens appearing in it are left untouched
+    template
+        .replace("{{ slug }}", slug)
+        .replace("{{ exit_code }}", &exit_code)
+        .replace("{{ stderr }}", stderr)
+}
+
+/// The `PATH` to run commands with, or `None` to inherit it unchanged.
+fn command_path(config: &ResolvedConfig) -> Option<String> {
+    if let Some(command_path) = &config.command_path {
+        return Some(command_path.clone());
+    }
+    if !config.modify_path {
+        return None;
+    }
+
+    let modified_path = get_modified_path(&config.data_dir)?;
+    tracing::debug!("Modify PATH environment variable to: {}", modified_path);
+    Some(modified_path)
+}
+
+fn get_modified_path(data_dir: &std::path::Path) -> Option<String> {
+    let Ok(current_path) = std::env::var("PATH") else {
+        tracing::warn!("Failed to read PATH environment variable");
+        return None;
+    };
+
+    let mut path_parts = Vec::new();
+
+    // Try to add current executable directory to PATH
+    match std::env::current_exe() {
+        Ok(current_exe) => {
+            match current_exe.parent() {
+                Some(exe_dir) => {
+                    let exe_dir_str = exe_dir.to_string_lossy();
+                    // Add exe_dir if not already in PATH
+                    if !current_path.split(':').any(|p| p == exe_dir_str) {
+                        path_parts.push(exe_dir_str.to_string());
+                    }
+                }
+                None => {
+                    tracing::warn!("Failed to get parent directory of executable");
+                }
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Failed to get current executable path: {}", e);
+        }
+    }
+
+    // Try to add data_dir to PATH
+    let data_dir_str = data_dir.to_string_lossy();
+    if !current_path.split(':').any(|p| p == data_dir_str) {
+        path_parts.push(data_dir_str.to_string());
+    }
+
+    // In case data directory might be the same as current executable directory
+    path_parts.dedup();
+
+    // Add the original PATH at the end
+    path_parts.push(current_path);
+
+    Some(path_parts.join(":"))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::test::TestClient;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::test_util::LogCapture;
+
+    /// The API serving `config`, with fresh runtime state.
+    fn app(config: ResolvedConfig) -> impl Endpoint {
+        let state = Arc::new(RuntimeState::new(&config));
+        super::app(Arc::new(ArcSwap::from_pointee(config)), state, true)
+    }
+
+    fn create_test_config() -> ResolvedConfig {
+        let outputs = HashMap::from([
+            ("echo-hello".to_string(), crate::config::OutputConfig {
+                slug: "echo-hello".to_string(),
+                cmd: Some("/bin/echo".to_string()),
+                args: Some(vec!["hello".to_string(), "world".to_string()]),
+                ..Default::default()
+            }),
+            ("pwd".to_string(), crate::config::OutputConfig {
+                slug: "pwd".to_string(),
+                cmd: Some("/bin/pwd".to_string()),
+                args: None,
+                ..Default::default()
+            }),
+        ]);
+
+        ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_json_banner() {
+        let config = create_test_config();
+        let banner = json_banner(&["127.0.0.1:7749".parse().unwrap()], &config);
+        assert!(!banner.contains('\n'));
+
+        let banner: serde_json::Value = serde_json::from_str(&banner).unwrap();
+        assert_eq!(banner["version"], crate::version());
+        assert_eq!(banner["api_addr"], "127.0.0.1:7749");
+        assert_eq!(banner["data_dir"], config.data_dir.to_str().unwrap());
+        assert_eq!(banner["outputs"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_config_endpoint() {
+        let config = create_test_config();
+        let app = app(config.clone());
+        let client = TestClient::new(app);
+
+        let resp = client.get("/config").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_content_type("application/json; charset=utf-8");
+
+        let returned_config: ResolvedConfig = resp.json().await.value().deserialize();
+        assert_eq!(returned_config.outputs.len(), config.outputs.len());
+        assert!(returned_config.outputs.contains_key("echo-hello"));
+        assert!(returned_config.outputs.contains_key("pwd"));
+    }
+
+    #[tokio::test]
+    async fn test_api_key_required_and_not_exposed() {
+        let config = ResolvedConfig {
+            api_key: Some("s3cret".to_string()),
+            ..create_test_config()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/echo-hello").send().await;
+        resp.assert_status(poem::http::StatusCode::UNAUTHORIZED);
+
+        let resp = client
+            .get("/config")
+            .header("Authorization", "Bearer s3cret")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let body = resp.0.into_body().into_string().await.unwrap();
+        assert!(!body.contains("s3cret"));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_run() {
+        let mut config = create_test_config();
+        config
+            .outputs
+            .insert("greet".to_string(), crate::config::OutputConfig {
+                slug: "greet".to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec![
+                    "-c".to_string(),
+                    "echo \"hello $JUNCTION_ARG_name\"".to_string(),
+                ]),
+                allow_query_env: true,
+                ..Default::default()
+            });
+        let client = TestClient::new(app(config));
+
+        let resp = client
+            .post("/rpc")
+            .body_json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "run",
+                "params": {"slug": "greet", "args": ["name=rpc"]},
+                "id": 7,
+            }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_json(serde_json::json!({
+            "jsonrpc": "2.0",
+            "result": "hello rpc\n",
+            "id": 7,
+        }))
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_rpc_errors() {
+        let client = TestClient::new(app(create_test_config()));
+
+        let resp = client
+            .post("/rpc")
+            .body_json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "run",
+                "params": {"slug": "nonexistent"},
+                "id": "a",
+            }))
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let json = resp.json().await;
+        let envelope = json.value().object();
+        envelope.get("id").assert_string("a");
+        let error = envelope.get("error").object();
+        error.get("code").assert_i64(-32000);
+        error.get("data").object().get("status").assert_i64(404);
+
+        let resp = client
+            .post("/rpc")
+            .body_json(&serde_json::json!({"jsonrpc": "2.0", "method": "stop", "id": 1}))
+            .send()
+            .await;
+        let json = resp.json().await;
+        let error = json.value().object().get("error").object();
+        error.get("code").assert_i64(-32601);
+
+        let resp = client.post("/rpc").body("not json").send().await;
+        let json = resp.json().await;
+        let envelope = json.value().object();
+        envelope.get("id").assert_null();
+        envelope
+            .get("error")
+            .object()
+            .get("code")
+            .assert_i64(-32700);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_drains_requests() {
+        let mut config = create_test_config();
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec!["-c".to_string(), "sleep 1; echo done".to_string()]),
+                ..Default::default()
+            });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = poem::listener::TcpAcceptor::from_tokio(listener).unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_acceptor(
+            acceptor,
+            config,
+            async {
+                let _ = shutdown_rx.await;
+            },
+            Some(Duration::from_secs(5)),
+            true,
+        ));
+
+        let request = tokio::spawn(async move {
+            let resp = reqwest::get(format!("http://{addr}/output/slow"))
+                .await
+                .unwrap();
+            (resp.status(), resp.text().await.unwrap())
+        });
+        // Let the request reach the server before shutting down
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shutdown_tx.send(()).unwrap();
+
+        let (status, body) = request.await.unwrap();
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body, "done\n");
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_multiple_addresses() {
+        let localhost: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let acceptor = bind_all(&[localhost, localhost])
+            .unwrap()
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addrs: Vec<_> = acceptor
+            .local_addr()
+            .into_iter()
+            .map(|addr| *addr.as_socket_addr().unwrap())
+            .collect();
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_acceptor(
+            acceptor,
+            create_test_config(),
+            async {
+                let _ = shutdown_rx.await;
+            },
+            None,
+            true,
+        ));
+
+        for addr in addrs {
+            let resp = reqwest::get(format!("http://{addr}/config")).await.unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        }
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_outputs_lists_slugs() {
+        let client = TestClient::new(app(create_test_config()));
+
+        let resp = client.get("/outputs").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_json(serde_json::json!([
+            {"slug": "echo-hello"},
+            {"slug": "pwd"},
+        ]))
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_get_help() {
+        let mut config = create_test_config();
+        let current = Arc::new(ArcSwap::from_pointee(config.clone()));
+        let client = TestClient::new(super::app(
+            current.clone(),
+            Arc::new(RuntimeState::default()),
+            true,
+        ));
+        let resp = client.get("/help").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+
+        // Enabled by a reload, without restarting
+        config.enable_help = true;
+        config.outputs.get_mut("pwd").unwrap().description =
+            Some("Working directory of commands".to_string());
+        current.store(Arc::new(config));
+        let resp = client.get("/help").send().await;
+        resp.assert_status_is_ok();
+        let help = resp.0.into_body().into_string().await.unwrap();
+        assert!(help.contains("GET  /output/<slug>"));
+        assert!(help.contains("\n  echo-hello\n  pwd  Working directory of commands\n"));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_existing_slug() {
+        let config = create_test_config();
+        let app = app(config);
+        let client = TestClient::new(app);
+
+        let resp = client.get("/output/echo-hello").send().await;
+
+        let status = resp.0.status();
+        let body = resp.0.into_body().into_string().await.unwrap();
+
+        // Debug: Print status and body if not OK
+        if status != poem::http::StatusCode::OK {
+            panic!("Expected OK, got {status}: {body}");
+        }
+
+        assert_eq!(status, poem::http::StatusCode::OK);
+        assert!(body.contains("hello world"));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_nonexistent_slug() {
+        let config = create_test_config();
+        let app = app(config);
+        let client = TestClient::new(app);
+
+        let resp = client.get("/output/nonexistent").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_with_pwd_command() {
+        let temp_dir = TempDir::new().unwrap();
+        let outputs = HashMap::from([("pwd".to_string(), crate::config::OutputConfig {
+            slug: "pwd".to_string(),
+            cmd: Some("/bin/pwd".to_string()),
+            args: None,
+            ..Default::default()
+        })]);
+
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let app = app(config);
+        let client = TestClient::new(app);
+
+        let resp = client.get("/output/pwd").send().await;
+
+        let status = resp.0.status();
+        let body = resp.0.into_body().into_string().await.unwrap();
+
+        if status != poem::http::StatusCode::OK {
+            panic!("Expected OK, got {status}: {body}");
+        }
+
+        assert_eq!(status, poem::http::StatusCode::OK);
+        assert!(body.contains(temp_dir.path().to_str().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_invalid_command() {
+        let outputs = HashMap::from([("invalid".to_string(), crate::config::OutputConfig {
+            slug: "invalid".to_string(),
+            cmd: Some("this-command-does-not-exist-12345".to_string()),
+            args: None,
+            ..Default::default()
+        })]);
+
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let app = app(config);
+        let client = TestClient::new(app);
+
+        let resp = client.get("/output/invalid").send().await;
+        resp.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_get_output_with_shebang_script() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let script_path = temp_dir.path().join("greet.sh");
+        std::fs::write(&script_path, "#!/bin/sh\necho \"greetings, $1\"\n").unwrap();
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let yaml = r#"
+verify_commands_on_start: true
+outputs:
+  - slug: "greet"
+    script: "greet.sh"
+    args: ["junction"]
+"#;
+        let config = crate::config::Config::from_yaml_str(yaml).unwrap();
+        let config = ResolvedConfig::new(config, temp_dir.path().to_path_buf()).unwrap();
+
+        let app = app(config);
+        let client = TestClient::new(app);
+
+        let resp = client.get("/output/greet").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("greetings, junction\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_create_working_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let yaml = r#"
+outputs:
+  - slug: "created"
+    cmd: "/bin/pwd"
+    working_dir: "reports/daily"
+    create_working_dir: true
+  - slug: "missing"
+    cmd: "/bin/pwd"
+    working_dir: "missing"
+"#;
+        let config = crate::config::Config::from_yaml_str(yaml).unwrap();
+        let config = ResolvedConfig::new(config, temp_dir.path().to_path_buf()).unwrap();
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/created").send().await;
+        resp.assert_status_is_ok();
+        let working_dir = temp_dir.path().join("reports/daily");
+        assert!(working_dir.is_dir());
+        let pwd = resp.0.into_body().into_string().await.unwrap();
+        assert_eq!(
+            std::path::Path::new(pwd.trim()).canonicalize().unwrap(),
+            working_dir.canonicalize().unwrap()
+        );
+
+        let resp = client.get("/output/missing").send().await;
+        resp.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!temp_dir.path().join("missing").exists());
+    }
+
+    #[tokio::test]
+    async fn test_get_output_fallback_after_failure() {
+        let outputs = HashMap::from([("fallback".to_string(), crate::config::OutputConfig {
+            slug: "fallback".to_string(),
+            cmd: Some("/bin/false".to_string()),
+            fallbacks: vec![
+                crate::config::OutputCommand {
+                    cmd: "this-command-does-not-exist-12345".to_string(),
+                    args: vec![],
+                },
+                crate::config::OutputCommand {
+                    cmd: "/bin/echo".to_string(),
+                    args: vec!["from fallback".to_string()],
+                },
+            ],
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/fallback").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("from fallback\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_all_fallbacks_fail() {
+        let outputs = HashMap::from([("fallback".to_string(), crate::config::OutputConfig {
+            slug: "fallback".to_string(),
+            cmd: Some("/bin/false".to_string()),
+            fallbacks: vec![crate::config::OutputCommand {
+                cmd: "this-command-does-not-exist-12345".to_string(),
+                args: vec![],
+            }],
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/fallback").send().await;
+        resp.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = resp.0.into_body().into_string().await.unwrap();
+        assert!(body.contains("/bin/false: "));
+        assert!(body.contains("this-command-does-not-exist-12345: Failed to execute command"));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_error_template() {
+        let data_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            data_dir.path().join("error.txt"),
+            "{{ slug }} failed ({{ exit_code }}): {{ stderr }}",
+        )
+        .unwrap();
+
+        let outputs = HashMap::from([("broken".to_string(), crate::config::OutputConfig {
+            slug: "broken".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo disk full >&2; exit 3".to_string(),
+            ]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: data_dir.path().to_path_buf(),
+            error_template: Some(data_dir.path().join("error.txt")),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/broken").send().await;
+        resp.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        resp.assert_text("broken failed (3): disk full\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_timeout() {
+        let outputs = HashMap::from([("hang".to_string(), crate::config::OutputConfig {
+            slug: "hang".to_string(),
+            cmd: Some("sleep".to_string()),
+            args: Some(vec!["10".to_string()]),
+            timeout_secs: Some(1),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let start = Instant::now();
+        let resp = client.get("/output/hang").send().await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        resp.assert_status(poem::http::StatusCode::GATEWAY_TIMEOUT);
+        resp.assert_text("command timed out after 1 seconds").await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_get_output_timeout_reaps_command() {
+        let data_dir = TempDir::new().unwrap();
+        let outputs = HashMap::from([("hang".to_string(), crate::config::OutputConfig {
+            slug: "hang".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo $$ > pid; exec sleep 10".to_string(),
+            ]),
+            timeout_secs: Some(1),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/hang").send().await;
+        resp.assert_status(poem::http::StatusCode::GATEWAY_TIMEOUT);
+
+        let pid: libc::pid_t = std::fs::read_to_string(data_dir.path().join("pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // Killed and already reaped, not left behind as a zombie
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_get_output_max_request_time() {
+        let data_dir = TempDir::new().unwrap();
+        let outputs = HashMap::from([("hang".to_string(), crate::config::OutputConfig {
+            slug: "hang".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo $$ > pid; exec sleep 10".to_string(),
+            ]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: data_dir.path().to_path_buf(),
+            max_request_time_secs: Some(1),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/hang").send().await;
+        resp.assert_status(poem::http::StatusCode::GATEWAY_TIMEOUT);
+        resp.assert_text("request took longer than 1 seconds").await;
+
+        let pid = wait_for_pid(&data_dir).await;
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_pass_request_meta() {
+        let outputs = HashMap::from([("meta".to_string(), crate::config::OutputConfig {
+            slug: "meta".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo \"$JUNCTION_REQUEST_METHOD $JUNCTION_REQUEST_PATH\"".to_string(),
+            ]),
+            pass_request_meta: true,
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/meta").query("limit", &1).send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("GET /output/meta\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_query_env() {
+        let shell = |slug: &str, script: &str| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec!["-c".to_string(), script.to_string()]),
+                allow_query_env: true,
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([
+            shell("report", "echo \"region=$JUNCTION_ARG_region\""),
+            shell("args", "env | grep ^JUNCTION_ARG_ | sort"),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client
+            .get("/output/report")
+            .query("region", &"us")
+            .query("format", &"csv")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_text("region=us\n").await;
+
+        // Parameters the server reads itself are not passed on
+        let resp = client
+            .get("/output/args")
+            .query("region", &"us")
+            .query("offset", &0)
+            .query("limit", &10)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_text("JUNCTION_ARG_region=us\n").await;
+
+        let resp = client
+            .get("/output/report")
+            .query("PATH=/tmp;x", &"1")
+            .send()
+            .await;
+        resp.assert_status(poem::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_query_env_global_allow_list() {
+        let config = crate::config::Config {
+            outputs: vec![crate::config::OutputConfig {
+                slug: "report".to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec![
+                    "-c".to_string(),
+                    "echo \"$JUNCTION_ARG_region $JUNCTION_ARG_format $JUNCTION_ARG_debug\""
+                        .to_string(),
+                ]),
+                allow_query_env: true,
+                allowed_query_params: vec!["region".to_string()],
+                ..Default::default()
+            }],
+            allowed_query_params: vec!["format".to_string()],
+            ..Default::default()
+        };
+        let config = ResolvedConfig::new(config, std::env::temp_dir()).unwrap();
+
+        let client = TestClient::new(app(config));
+        let resp = client
+            .get("/output/report")
+            .query("region", &"us")
+            .query("format", &"csv")
+            .query("debug", &"1")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_text("us csv \n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_query_env_disabled() {
+        let outputs = HashMap::from([("report".to_string(), crate::config::OutputConfig {
+            slug: "report".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo \"region=$JUNCTION_ARG_region\"".to_string(),
+            ]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client
+            .get("/output/report")
+            .query("region", &"us")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_text("region=\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_proxy() {
+        let upstream = Route::new()
+            .at(
+                "/output/report",
+                poem::endpoint::make_sync(|_| Json(serde_json::json!({"rows": 3}))),
+            )
+            .at(
+                "/output/missing",
+                poem::endpoint::make_sync(|_| poem::http::StatusCode::NOT_FOUND),
+            );
+        let upstream_addr = crate::test_util::spawn_server(upstream).await;
+
+        let proxy = |slug: &str, upstream_slug: &str| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                proxy_url: Some(format!("http://{upstream_addr}/output/{upstream_slug}")),
+                ..Default::default()
+            })
+        };
+        let config = ResolvedConfig {
+            outputs: HashMap::from([proxy("report", "report"), proxy("gone", "missing")]),
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/report").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_content_type("application/json; charset=utf-8");
+        resp.assert_text(r#"{"rows":3}"#).await;
+
+        let resp = client.get("/output/gone").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_etag() {
+        let client = TestClient::new(app(create_test_config()));
+        let resp = client.get("/output/echo-hello").send().await;
+        resp.assert_status_is_ok();
+        let etag = resp.0.headers().get("ETag").unwrap().clone();
+        // sha256sum of "hello world\n"
+        assert_eq!(
+            etag,
+            "\"a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447\""
+        );
+        resp.assert_text("hello world\n").await;
+
+        let resp = client
+            .get("/output/echo-hello")
+            .header("If-None-Match", etag.clone())
+            .send()
+            .await;
+        resp.assert_status(poem::http::StatusCode::NOT_MODIFIED);
+        resp.assert_header("ETag", etag.clone());
+        resp.assert_text("").await;
+
+        let resp = client
+            .get("/output/echo-hello")
+            .header("If-None-Match", "\"stale\"")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("ETag", etag);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_webhook() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let webhook = poem::endpoint::make(move |req: Request| {
+            let tx = tx.clone();
+            async move {
+                let slug = req
+                    .header("X-Junction-Slug")
+                    .unwrap_or_default()
+                    .to_string();
+                let body = req.into_body().into_string().await.unwrap();
+                tx.send((slug, body)).unwrap();
+            }
+        });
+        let webhook_addr = crate::test_util::spawn_server(webhook).await;
+
+        let outputs = HashMap::from([("hello".to_string(), crate::config::OutputConfig {
+            slug: "hello".to_string(),
+            cmd: Some("/bin/echo".to_string()),
+            args: Some(vec!["hello".to_string()]),
+            webhook_url: Some(format!("http://{webhook_addr}/hook")),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/hello").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("hello\n").await;
+
+        let received = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("webhook was not called")
+            .unwrap();
+        assert_eq!(received, ("hello".to_string(), "hello\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_content_type() {
+        let outputs = HashMap::from([("json".to_string(), crate::config::OutputConfig {
+            slug: "json".to_string(),
+            cmd: Some("/bin/echo".to_string()),
+            args: Some(vec![r#"{"ok":true}"#.to_string()]),
+            content_type: Some("application/json".to_string()),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/json").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_content_type("application/json");
+        resp.assert_text("{\"ok\":true}\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_minify() {
+        let pretty = |slug: &str, minify, json: &str| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/printf".to_string()),
+                args: Some(vec![json.to_string()]),
+                minify,
+                ..Default::default()
+            })
+        };
+        let json = r#"{\n  "ok": true,\n  "rows": [1, 2]\n}\n"#;
+        let outputs = HashMap::from([
+            pretty("minified", Minify::Json, json),
+            pretty("untouched", Minify::None, json),
+            pretty("invalid", Minify::Json, "not json"),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/minified").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text(r#"{"ok":true,"rows":[1,2]}"#).await;
+
+        let resp = client.get("/output/untouched").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("{\n  \"ok\": true,\n  \"rows\": [1, 2]\n}\n")
+            .await;
+
+        let resp = client.get("/output/invalid").send().await;
+        resp.assert_status(poem::http::StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_line_endings() {
+        let printf = |slug: &str, line_endings, text: &str| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/printf".to_string()),
+                args: Some(vec![text.to_string()]),
+                line_endings,
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([
+            printf("to-lf", LineEndings::Lf, r"a\r\nb\nc\r\n"),
+            printf("to-crlf", LineEndings::Crlf, r"a\r\nb\nc\r"),
+            printf("preserved", LineEndings::Preserve, r"a\r\nb\n"),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/to-lf").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("a\nb\nc\n").await;
+
+        let resp = client.get("/output/to-crlf").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("a\r\nb\r\nc\r").await;
+
+        let resp = client.get("/output/preserved").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("a\r\nb\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_working_dir_header() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("reports")).unwrap();
+        let yaml = r#"
+outputs:
+  - slug: "report"
+    cmd: "/bin/echo"
+    working_dir: "reports"
+"#;
+        let config = crate::config::Config::from_yaml_str(yaml).unwrap();
+        let mut config = ResolvedConfig::new(config, temp_dir.path().to_path_buf()).unwrap();
+
+        // Only reported with allow_debug
+        let resp = TestClient::new(app(config.clone()))
+            .get("/output/report")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header_is_not_exist("X-Working-Dir");
+
+        config.allow_debug = true;
+        let resp = TestClient::new(app(config))
+            .get("/output/report")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header(
+            "X-Working-Dir",
+            temp_dir.path().join("reports").display().to_string(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_output_content_type_override() {
+        let outputs = HashMap::from([("json".to_string(), crate::config::OutputConfig {
+            slug: "json".to_string(),
+            cmd: Some("/bin/echo".to_string()),
+            args: Some(vec![r#"{"ok":true}"#.to_string()]),
+            content_type: Some("application/octet-stream".to_string()),
+            ..Default::default()
+        })]);
+        let mut config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        // Ignored unless allow_debug is set
+        let client = TestClient::new(app(config.clone()));
+        let resp = client
+            .get("/output/json")
+            .query("content_type", &"text/plain")
+            .send()
+            .await;
+        resp.assert_content_type("application/octet-stream");
+
+        config.allow_debug = true;
+        let client = TestClient::new(app(config));
+        let resp = client
+            .get("/output/json")
+            .query("content_type", &"text/plain")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_content_type("text/plain");
+
+        let resp = client.get("/output/json").send().await;
+        resp.assert_content_type("application/octet-stream");
+
+        let resp = client
+            .get("/output/json")
+            .query("content_type", &"not a type")
+            .send()
+            .await;
+        resp.assert_status(poem::http::StatusCode::BAD_REQUEST);
+    }
+
+    /// An output appending a line to `runs` in `data_dir` every time it runs.
+    fn counting_output(cache_ttl_secs: u64, exit_code: u8) -> crate::config::OutputConfig {
+        crate::config::OutputConfig {
+            slug: "counted".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                format!("date +%s%N >> runs; echo ran; exit {exit_code}"),
+            ]),
+            cache_ttl_secs: Some(cache_ttl_secs),
+            ..Default::default()
+        }
+    }
+
+    fn count_runs(data_dir: &TempDir) -> usize {
+        std::fs::read_to_string(data_dir.path().join("runs"))
+            .unwrap_or_default()
+            .lines()
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_get_output_async_refresh() {
+        let data_dir = TempDir::new().unwrap();
+        let mut output = counting_output(1, 0);
+        // Every run after the first is slow
+        output.args.as_mut().unwrap()[1] =
+            "date +%s%N >> runs; [ $(wc -l < runs) -gt 1 ] && sleep 1; wc -l < runs".to_string();
+        output.async_refresh = true;
+        let config = ResolvedConfig {
+            outputs: HashMap::from([("counted".to_string(), output)]),
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/counted").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_header_is_not_exist("X-Cache");
+        resp.assert_text("1\n").await;
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        for _ in 0..2 {
+            let start = Instant::now();
+            let resp = client.get("/output/counted").send().await;
+            resp.assert_status(poem::http::StatusCode::ACCEPTED);
+            resp.assert_header("X-Cache", "stale");
+            resp.assert_text("1\n").await;
+            assert!(start.elapsed() < Duration::from_millis(500));
+        }
+
+        for _ in 0..100 {
+            let resp = client.get("/output/counted").send().await;
+            if resp.0.status() == poem::http::StatusCode::OK {
+                resp.assert_text("2\n").await;
+                // Stale requests during the refresh didn't start another one
+                assert_eq!(count_runs(&data_dir), 2);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("output was not refreshed");
+    }
+
+    #[tokio::test]
+    async fn test_get_output_async_refresh_min_interval() {
+        let data_dir = TempDir::new().unwrap();
+        let mut output = counting_output(1, 0);
+        output.async_refresh = true;
+        output.min_interval_secs = Some(60);
+        let config = ResolvedConfig {
+            outputs: HashMap::from([("counted".to_string(), output)]),
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        for _ in 0..3 {
+            let resp = client.get("/output/counted").send().await;
+            resp.assert_status(poem::http::StatusCode::ACCEPTED);
+            resp.assert_text("ran\n").await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // Background refreshes are runs too, so none started this early
+        assert_eq!(count_runs(&data_dir), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_cache() {
+        let data_dir = TempDir::new().unwrap();
+        let mut other = counting_output(60, 0);
+        other.slug = "other".to_string();
+        other.args.as_mut().unwrap()[1] = "date +%s%N >> other-runs; echo ran".to_string();
+        let config = ResolvedConfig {
+            outputs: HashMap::from([
+                ("counted".to_string(), counting_output(60, 0)),
+                ("other".to_string(), other),
+            ]),
+            data_dir: data_dir.path().to_path_buf(),
+            api_key: Some("s3cret".to_string()),
+            ..Default::default()
+        };
+        let count_other_runs = || {
+            std::fs::read_to_string(data_dir.path().join("other-runs"))
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+
+        let client = TestClient::new(app(config));
+        let get_both = || async {
+            for slug in ["counted", "other"] {
+                client
+                    .get(format!("/output/{slug}"))
+                    .header("Authorization", "Bearer s3cret")
+                    .send()
+                    .await
+                    .assert_status_is_ok();
+            }
+        };
+        let invalidate = |body: &'static str| {
+            client
+                .post("/cache/invalidate")
+                .header("Authorization", "Bearer s3cret")
+                .body(body)
+                .send()
+        };
+
+        get_both().await;
+        get_both().await;
+        assert_eq!((count_runs(&data_dir), count_other_runs()), (1, 1));
+
+        let resp = invalidate(r#"{"slug": "counted"}"#).await;
+        resp.assert_status_is_ok();
+        resp.assert_json(serde_json::json!({"invalidated": 1}))
+            .await;
+        get_both().await;
+        assert_eq!((count_runs(&data_dir), count_other_runs()), (2, 1));
+
+        let resp = invalidate("").await;
+        resp.assert_json(serde_json::json!({"invalidated": 2}))
+            .await;
+        get_both().await;
+        assert_eq!((count_runs(&data_dir), count_other_runs()), (3, 2));
+
+        invalidate("not json")
+            .await
+            .assert_status(poem::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_cached_within_ttl() {
+        let data_dir = TempDir::new().unwrap();
+        let config = ResolvedConfig {
+            outputs: HashMap::from([("counted".to_string(), counting_output(60, 0))]),
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        for _ in 0..3 {
+            let resp = client.get("/output/counted").send().await;
+            resp.assert_status_is_ok();
+            resp.assert_text("ran\n").await;
+        }
+        assert_eq!(count_runs(&data_dir), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_cache_inputs() {
+        let data_dir = TempDir::new().unwrap();
+        let input = data_dir.path().join("input.txt");
+        std::fs::write(&input, "v1").unwrap();
+        let mut output = counting_output(60, 0);
+        output.cache_ttl_secs = None;
+        output.cache_inputs = vec!["input.txt".into()];
+        let config = ResolvedConfig {
+            outputs: HashMap::from([("counted".to_string(), output)]),
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        for _ in 0..2 {
+            client
+                .get("/output/counted")
+                .send()
+                .await
+                .assert_status_is_ok();
+        }
+        assert_eq!(count_runs(&data_dir), 1);
+
+        let file = std::fs::File::options().write(true).open(&input).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert_eq!(count_runs(&data_dir), 2);
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert_eq!(count_runs(&data_dir), 2);
+
+        std::fs::remove_file(&input).unwrap();
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert_eq!(count_runs(&data_dir), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_cache_expires() {
+        let data_dir = TempDir::new().unwrap();
+        let config = ResolvedConfig {
+            outputs: HashMap::from([("counted".to_string(), counting_output(1, 0))]),
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert_eq!(count_runs(&data_dir), 1);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        client
+            .get("/output/counted")
+            .send()
+            .await
+            .assert_status_is_ok();
+        assert_eq!(count_runs(&data_dir), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_errors_not_cached() {
+        let data_dir = TempDir::new().unwrap();
+        let config = ResolvedConfig {
+            outputs: HashMap::from([("counted".to_string(), counting_output(60, 1))]),
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        for _ in 0..2 {
+            let resp = client.get("/output/counted").send().await;
+            resp.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        assert_eq!(count_runs(&data_dir), 2);
+    }
+
+    #[test]
+    fn test_sample_log_rate() {
+        use rand::SeedableRng;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let logged = (0..10_000)
+            .filter(|_| sample_log(Some(0.1), &mut rng))
+            .count();
+        assert!((900..1100).contains(&logged), "logged {logged} of 10000");
+
+        assert!((0..100).all(|_| sample_log(None, &mut rng)));
+        assert!((0..100).all(|_| sample_log(Some(1.0), &mut rng)));
+        assert!((0..100).all(|_| !sample_log(Some(0.0), &mut rng)));
+    }
+
+    /// A spawn function failing with `kind` the first `failures` times.
+    fn failing_spawn(
+        failures: u32,
+        kind: std::io::ErrorKind,
+        attempts: &mut u32,
+    ) -> impl FnMut() -> std::io::Result<()> + '_ {
+        move || {
+            *attempts += 1;
+            if *attempts <= failures {
+                Err(std::io::Error::from(kind))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_spawn() {
+        use std::io::ErrorKind;
+
+        let mut attempts = 0;
+        let result = retry_spawn(failing_spawn(2, ErrorKind::WouldBlock, &mut attempts)).await;
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result = retry_spawn(failing_spawn(5, ErrorKind::WouldBlock, &mut attempts)).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(attempts, SPAWN_ATTEMPTS);
+
+        // Only resource exhaustion is retried
+        let mut attempts = 0;
+        let result = retry_spawn(failing_spawn(1, ErrorKind::NotFound, &mut attempts)).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_runs_in_work_dir() {
+        let data_dir = TempDir::new().unwrap();
+        let work_dir = TempDir::new().unwrap();
+        let outputs = HashMap::from([("pwd".to_string(), crate::config::OutputConfig {
+            slug: "pwd".to_string(),
+            cmd: Some("pwd".to_string()),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: data_dir.path().to_path_buf(),
+            work_dir: Some(work_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/pwd").send().await;
+        resp.assert_status_is_ok();
+        let body = resp.0.into_body().into_string().await.unwrap();
+        assert_eq!(
+            std::fs::canonicalize(body.trim()).unwrap(),
+            std::fs::canonicalize(work_dir.path()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gzip_compression() {
+        use std::io::Read;
+
+        use http_body_util::BodyExt;
+
+        let seq = |slug: &str, stream, stream_error_mode| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("seq".to_string()),
+                args: Some(vec!["1000".to_string()]),
+                stream,
+                stream_error_mode,
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([
+            seq("numbers", false, StreamErrorMode::Trailer),
+            seq("streamed", true, StreamErrorMode::Trailer),
+            seq("events", true, StreamErrorMode::Event),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let expected: String = (1..=1000).map(|n| format!("{n}\n")).collect();
+
+        let client = TestClient::new(app(config.clone()));
+        let resp = client
+            .get("/output/numbers")
+            .header("Accept-Encoding", "gzip")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("Content-Encoding", "gzip");
+        let decompress = |compressed: Vec<u8>| {
+            let mut body = String::new();
+            flate2::read::GzDecoder::new(compressed.as_slice())
+                .read_to_string(&mut body)
+                .unwrap();
+            body
+        };
+        assert_eq!(
+            decompress(resp.0.into_body().into_vec().await.unwrap()),
+            expected
+        );
+
+        for slug in ["streamed", "events"] {
+            let resp = client
+                .get(format!("/output/{slug}"))
+                .header("Accept-Encoding", "gzip")
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            resp.assert_header("Content-Encoding", "gzip");
+            assert_eq!(
+                decompress(resp.0.into_body().into_vec().await.unwrap()),
+                expected
+            );
+        }
+
+        // Compressing would drop the exit code trailer the client asked for
+        let resp = client
+            .get("/output/streamed")
+            .header("Accept-Encoding", "gzip")
+            .header("TE", "trailers")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header_is_not_exist("Content-Encoding");
+        let body = BoxBody::from(resp.0.into_body()).collect().await.unwrap();
+        assert_eq!(body.trailers().unwrap()["X-Exit-Code"], "0");
+        assert_eq!(body.to_bytes(), expected);
+
+        let uncompressed = super::app(
+            Arc::new(ArcSwap::from_pointee(config)),
+            Arc::new(RuntimeState::default()),
+            false,
+        );
+        let resp = TestClient::new(uncompressed)
+            .get("/output/numbers")
+            .header("Accept-Encoding", "gzip")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header_is_not_exist("Content-Encoding");
+        resp.assert_text(expected).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_stream_large() {
+        let outputs = HashMap::from([("numbers".to_string(), crate::config::OutputConfig {
+            slug: "numbers".to_string(),
+            cmd: Some("seq".to_string()),
+            args: Some(vec!["200000".to_string()]),
+            stream: true,
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/numbers").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_content_type("text/plain; charset=utf-8");
+        let body = resp.0.into_body().into_string().await.unwrap();
+        let expected: String = (1..=200000).map(|n| format!("{n}\n")).collect();
+        assert_eq!(body.len(), expected.len());
+        assert!(body == expected);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_stream_exit_code_trailer() {
+        use http_body_util::BodyExt;
+
+        let outputs = HashMap::from([("partial".to_string(), crate::config::OutputConfig {
+            slug: "partial".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec!["-c".to_string(), "echo partial; exit 3".to_string()]),
+            stream: true,
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/partial").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_header("Trailer", "X-Exit-Code");
+
+        let body = BoxBody::from(resp.0.into_body()).collect().await.unwrap();
+        let trailers = body.trailers().cloned().unwrap();
+        assert_eq!(trailers["X-Exit-Code"], "3");
+        assert_eq!(body.to_bytes(), "partial\n");
+    }
+
+    #[tokio::test]
+    async fn test_get_output_stream_error_mode() {
+        use http_body_util::BodyExt;
+
+        let interrupted = |slug: &str, stream_error_mode| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec![
+                    "-c".to_string(),
+                    "echo partial; exec sleep 5".to_string(),
+                ]),
+                stream: true,
+                stream_error_mode,
+                timeout_secs: Some(1),
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([
+            interrupted("trailer", StreamErrorMode::Trailer),
+            interrupted("event", StreamErrorMode::Event),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        // The killed command has no exit code to send
+        let resp = client.get("/output/trailer").send().await;
+        resp.assert_status_is_ok();
+        let body = BoxBody::from(resp.0.into_body()).collect().await.unwrap();
+        assert!(body
+            .trailers()
+            .is_none_or(|trailers| !trailers.contains_key("X-Exit-Code")));
+        assert_eq!(body.to_bytes(), "partial\n");
+
+        let resp = client.get("/output/event").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("partial\nevent: error\ndata: command was killed\n\n")
+            .await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_get_output_stream_client_disconnect() {
+        use tokio::io::AsyncWriteExt;
+
+        let data_dir = TempDir::new().unwrap();
+        let outputs = HashMap::from([("slow".to_string(), crate::config::OutputConfig {
+            slug: "slow".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            // Ignoring SIGPIPE keeps the command running after its reader
+            // is gone
+            args: Some(vec![
+                "-c".to_string(),
+                "trap '' PIPE; echo $$ > pid; while true; do echo tick; sleep 0.1; done"
+                    .to_string(),
+            ]),
+            stream: true,
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let addr = crate::test_util::spawn_server(app(config)).await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /output/slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        while !String::from_utf8_lossy(&received).contains("tick") {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        let pid: libc::pid_t = std::fs::read_to_string(data_dir.path().join("pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        drop(stream);
+
+        for _ in 0..100 {
+            if unsafe { libc::kill(pid, 0) } == -1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_stream_failure_logged() {
+        let (capture, _guard) = LogCapture::install();
+        let outputs = HashMap::from([("partial".to_string(), crate::config::OutputConfig {
+            slug: "partial".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo partial; echo broken >&2; exit 2".to_string(),
+            ]),
+            stream: true,
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/partial").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("partial\n").await;
+
+        for _ in 0..100 {
+            if capture
+                .contents()
+                .contains("Streamed command for output partial failed")
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let logs = capture.contents();
+        assert!(logs.contains("Streamed command for output partial failed"));
+        assert!(logs.contains("broken"));
+    }
+
+    #[tokio::test]
+    async fn test_post_output_body_as_stdin() {
+        let cat = |slug: &str, accept_stdin: bool| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/cat".to_string()),
+                accept_stdin,
+                ..Default::default()
+            })
+        };
+        let config = ResolvedConfig {
+            outputs: HashMap::from([cat("echo", true), cat("closed", false)]),
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client
+            .post("/output/echo")
+            .body("piped through cat\n")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_text("piped through cat\n").await;
+
+        let resp = client.post("/output/closed").body("ignored").send().await;
+        resp.assert_status(poem::http::StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_pagination() {
+        let outputs = HashMap::from([("lines".to_string(), crate::config::OutputConfig {
+            slug: "lines".to_string(),
+            cmd: Some("/usr/bin/seq".to_string()),
+            args: Some(vec!["10".to_string()]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client
+            .get("/output/lines")
+            .query("offset", &3)
+            .query("limit", &2)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("X-Total-Lines", "10");
+        resp.assert_text("4\n5\n").await;
+
+        let resp = client.get("/output/lines").query("offset", &8).send().await;
+        resp.assert_header("X-Total-Lines", "10");
+        resp.assert_text("9\n10\n").await;
+
+        let resp = client.get("/output/lines").send().await;
+        resp.assert_header_is_not_exist("X-Total-Lines");
+        resp.assert_text("1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n").await;
+    }
+
+    #[test]
+    fn test_paginate_lines() {
+        let pagination = Pagination {
+            offset: Some(1),
+            limit: Some(5),
+        };
+        assert_eq!(
+            paginate_lines("a\nb\r\nc", &pagination),
+            ("b\r\nc".to_string(), 3)
+        );
+        assert_eq!(paginate_lines("", &pagination), (String::new(), 0));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_min_interval() {
+        let outputs = HashMap::from([("expensive".to_string(), crate::config::OutputConfig {
+            slug: "expensive".to_string(),
+            cmd: Some("/bin/echo".to_string()),
+            args: Some(vec!["done".to_string()]),
+            min_interval_secs: Some(60),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/expensive").send().await;
+        resp.assert_status_is_ok();
+
+        let resp = client.get("/output/expensive").send().await;
+        resp.assert_status(poem::http::StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = resp
+            .0
+            .headers()
+            .get("Retry-After")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+    }
+
+    #[tokio::test]
+    async fn test_get_output_empty_output_modes() {
+        let silent = |slug: &str, empty_output| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/true".to_string()),
+                empty_output,
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([
+            silent("ok", EmptyOutput::Ok),
+            silent("no-content", EmptyOutput::NoContent),
+            silent("error", EmptyOutput::Error),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/ok").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("").await;
+
+        let resp = client.get("/output/no-content").send().await;
+        resp.assert_status(poem::http::StatusCode::NO_CONTENT);
+
+        let resp = client.get("/output/error").send().await;
+        resp.assert_status(poem::http::StatusCode::BAD_GATEWAY);
+        resp.assert_text("Command for output error produced no output")
+            .await;
+    }
+
+    #[cfg(feature = "flags")]
+    #[tokio::test]
+    async fn test_get_output_enabled_flag() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
+
+        let beta_on = Arc::new(AtomicBool::new(true));
+        let flags_service = {
+            let beta_on = beta_on.clone();
+            Route::new().at(
+                "/flags/beta",
+                poem::endpoint::make_sync(move |_| beta_on.load(Ordering::SeqCst).to_string()),
+            )
+        };
+        let flags_addr = crate::test_util::spawn_server(flags_service).await;
+
+        let flagged = |slug: &str, flag: &str| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/echo".to_string()),
+                args: Some(vec![slug.to_string()]),
+                enabled_flag: Some(flag.to_string()),
+                ..Default::default()
+            })
+        };
+        let config = ResolvedConfig {
+            outputs: HashMap::from([flagged("beta", "beta"), flagged("unknown", "unknown")]),
+            data_dir: std::env::temp_dir(),
+            flags_url: Some(format!("http://{flags_addr}/flags")),
+            flags_cache_secs: Some(0),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/beta").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("beta\n").await;
+
+        beta_on.store(false, Ordering::SeqCst);
+        let resp = client.get("/output/beta").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+
+        beta_on.store(true, Ordering::SeqCst);
+        let resp = client.get("/output/beta").send().await;
+        resp.assert_status_is_ok();
+
+        // Flags the service does not know about are off
+        let resp = client.get("/output/unknown").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_get_output_with_pty() {
+        let is_tty = |slug: &str, pty| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec![
+                    "-c".to_string(),
+                    "if [ -t 1 ]; then echo tty; else echo pipe; fi".to_string(),
+                ]),
+                pty,
+                ..Default::default()
+            })
+        };
+        let config = ResolvedConfig {
+            outputs: HashMap::from([is_tty("pty", true), is_tty("pipe", false)]),
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/pty").send().await;
+        resp.assert_status_is_ok();
+        // The terminal translates newlines to CRLF
+        resp.assert_text("tty\r\n").await;
+
+        let resp = client.get("/output/pipe").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("pipe\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_artificial_delay() {
+        let outputs = HashMap::from([("slow".to_string(), crate::config::OutputConfig {
+            slug: "slow".to_string(),
+            cmd: Some("/bin/echo".to_string()),
+            args: Some(vec!["eventually".to_string()]),
+            artificial_delay_ms: Some(300),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let start = Instant::now();
+        let resp = client.get("/output/slow").send().await;
+        let elapsed = start.elapsed();
+
+        resp.assert_status_is_ok();
+        resp.assert_text("eventually\n").await;
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(elapsed < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_get_output_stdin_url() {
+        let upstream = Route::new().at(
+            "/source",
+            poem::endpoint::make_sync(|_| "remote content\nsecond line\n"),
+        );
+        let upstream_addr = crate::test_util::spawn_server(upstream).await;
+
+        let cat = |slug: &str, stdin_url: String| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/cat".to_string()),
+                stdin_url: Some(stdin_url),
+                ..Default::default()
+            })
+        };
+        let config = ResolvedConfig {
+            outputs: HashMap::from([
+                cat("piped", format!("http://{upstream_addr}/source")),
+                cat("missing", format!("http://{upstream_addr}/missing")),
+            ]),
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        let client = TestClient::new(app(config));
+
+        let resp = client.get("/output/piped").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("remote content\nsecond line\n").await;
+
+        let resp = client.get("/output/missing").send().await;
+        resp.assert_status(poem::http::StatusCode::BAD_GATEWAY);
+    }
+
+    /// Reads the pid a command wrote to `pid` in `data_dir`, waiting for it
+    /// to start.
+    #[cfg(unix)]
+    async fn wait_for_pid(data_dir: &TempDir) -> libc::pid_t {
+        let pid_file = data_dir.path().join("pid");
+        for _ in 0..100 {
+            if let Ok(contents) = std::fs::read_to_string(&pid_file) {
+                if let Ok(pid) = contents.trim().parse() {
+                    return pid;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("command did not start");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_output_cancelled() {
+        let data_dir = TempDir::new().unwrap();
+        let outputs = HashMap::from([("slow".to_string(), crate::config::OutputConfig {
+            slug: "slow".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo $$ > pid; exec sleep 10".to_string(),
+            ]),
+            ..Default::default()
+        })]);
+        let config = Arc::new(ResolvedConfig {
+            outputs,
+            data_dir: data_dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        let state = Arc::new(RuntimeState::new(&config));
+
+        let cancel = CancellationToken::new();
+        let execution = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                let request = OutputRequest::default();
+                run_output_cancellable(&config, &state, "slow", &request, &cancel).await
+            }
+        });
+        let pid = wait_for_pid(&data_dir).await;
+
+        cancel.cancel();
+        let result = tokio::time::timeout(Duration::from_secs(5), execution)
+            .await
+            .expect("cancelled request did not stop")
+            .unwrap();
+        assert!(result.is_err());
+        // The child has been killed and reaped
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+
+    #[tokio::test]
+    async fn test_post_output_max_stdin_bytes() {
+        let cat = |slug: &str, max_stdin_bytes| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("/bin/cat".to_string()),
+                accept_stdin: true,
+                max_stdin_bytes,
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([cat("strict", Some(4)), cat("lenient", Some(1024))]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.post("/output/strict").body("abcd").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("abcd").await;
+
+        let resp = client.post("/output/strict").body("abcde").send().await;
+        resp.assert_status(poem::http::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let resp = client.post("/output/lenient").body("abcde").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("abcde").await;
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_commands_queues() {
+        let mut config = create_test_config();
+        config.max_concurrent_commands = Some(2);
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec!["-c".to_string(), "sleep 0.5; echo done".to_string()]),
+                ..Default::default()
+            });
+        let app = Arc::new(app(config));
+
+        let start = Instant::now();
+        let requests = (0..3).map(|_| {
+            let client = TestClient::new(app.clone());
+            tokio::spawn(async move {
+                let resp = client.get("/output/slow").send().await;
+                resp.assert_status_is_ok();
+                resp.assert_text("done\n").await;
+            })
+        });
+        for request in requests.collect::<Vec<_>>() {
+            request.await.unwrap();
+        }
+        // The third command only starts once one of the first two finished
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_commands_queue_timeout() {
+        let mut config = create_test_config();
+        config.max_concurrent_commands = Some(1);
+        config.queue_timeout_secs = Some(1);
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sleep".to_string()),
+                args: Some(vec!["3".to_string()]),
+                ..Default::default()
+            });
+        let app = Arc::new(app(config));
+
+        let slow_client = TestClient::new(app.clone());
+        let slow_request = tokio::spawn(async move {
+            slow_client
+                .get("/output/slow")
+                .send()
+                .await
+                .assert_status_is_ok();
+        });
+
+        let client = TestClient::new(app);
+        for _ in 0..100 {
+            let resp = client.get("/status").send().await;
+            let json = resp.json().await;
+            if json
+                .value()
+                .object()
+                .get("in_flight")
+                .object()
+                .get("total")
+                .i64()
+                > 0
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let start = Instant::now();
+        let resp = client.get("/output/echo-hello").send().await;
+        resp.assert_status(poem::http::StatusCode::SERVICE_UNAVAILABLE);
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_secs(1));
+        assert!(waited < Duration::from_secs(3));
+
+        slow_request.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_commands_rejects() {
+        let mut config = create_test_config();
+        config.max_concurrent_commands = Some(1);
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sleep".to_string()),
+                args: Some(vec!["1".to_string()]),
+                ..Default::default()
+            });
+        config
+            .outputs
+            .insert("eager".to_string(), crate::config::OutputConfig {
+                slug: "eager".to_string(),
+                cmd: Some("/bin/echo".to_string()),
+                reject_when_busy: true,
+                ..Default::default()
+            });
+        let app = Arc::new(app(config));
+
+        let slow_client = TestClient::new(app.clone());
+        let slow_request = tokio::spawn(async move {
+            slow_client
+                .get("/output/slow")
+                .send()
+                .await
+                .assert_status_is_ok();
+        });
+
+        let client = TestClient::new(app);
+        for _ in 0..100 {
+            let resp = client.get("/status").send().await;
+            let json = resp.json().await;
+            if json
+                .value()
+                .object()
+                .get("in_flight")
+                .object()
+                .get("total")
+                .i64()
+                > 0
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let resp = client.get("/output/eager").send().await;
+        resp.assert_status(poem::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        slow_request.await.unwrap();
+        client
+            .get("/output/eager")
+            .send()
+            .await
+            .assert_status_is_ok();
+    }
+
+    #[tokio::test]
+    async fn test_post_output_max_uploads() {
+        let mut config = create_test_config();
+        config.max_uploads = Some(1);
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sh".to_string()),
+                args: Some(vec!["-c".to_string(), "sleep 1; cat".to_string()]),
+                accept_stdin: true,
+                ..Default::default()
+            });
+        let app = Arc::new(app(config));
+
+        let slow_client = TestClient::new(app.clone());
+        let slow_request = tokio::spawn(async move {
+            let resp = slow_client.post("/output/slow").body("first").send().await;
+            resp.assert_status_is_ok();
+            resp.assert_text("first").await;
+        });
+
+        let client = TestClient::new(app);
+        for _ in 0..100 {
+            let resp = client.get("/status").send().await;
+            let json = resp.json().await;
+            if json
+                .value()
+                .object()
+                .get("in_flight")
+                .object()
+                .get("total")
+                .i64()
+                > 0
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let resp = client.post("/output/slow").body("second").send().await;
+        resp.assert_status(poem::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        slow_request.await.unwrap();
+        let resp = client.post("/output/slow").body("third").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("third").await;
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_in_flight() {
+        let mut config = create_test_config();
+        config.max_connections = Some(32);
+        config.max_concurrent_commands = Some(4);
+        config.queue_timeout_secs = Some(5);
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sleep".to_string()),
+                args: Some(vec!["1".to_string()]),
+                ..Default::default()
+            });
+        let app = Arc::new(app(config));
+
+        let slow_client = TestClient::new(app.clone());
+        let slow_request =
+            tokio::spawn(async move { slow_client.get("/output/slow").send().await.0.status() });
+
+        let client = TestClient::new(app);
+        let mut in_flight = 0;
+        for _ in 0..100 {
+            let resp = client.get("/status").send().await;
+            resp.assert_status_is_ok();
+            let json = resp.json().await;
+            let status = json.value().object();
+            in_flight = status.get("in_flight").object().get("total").i64();
+            if in_flight > 0 {
+                status
+                    .get("in_flight")
+                    .object()
+                    .get("outputs")
+                    .object()
+                    .get("slow")
+                    .assert_i64(1);
+                let limits = status.get("limits").object();
+                limits.get("max_connections").assert_i64(32);
+                limits.get("max_concurrent_commands").assert_i64(4);
+                limits.get("queue_timeout_secs").assert_i64(5);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(in_flight, 1);
+
+        assert_eq!(slow_request.await.unwrap(), poem::http::StatusCode::OK);
+        let resp = client.get("/status").send().await;
+        resp.json()
+            .await
+            .value()
+            .object()
+            .get("in_flight")
+            .object()
+            .get("total")
+            .assert_i64(0);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_streamed_in_flight() {
+        let mut config = create_test_config();
+        config
+            .outputs
+            .insert("slow".to_string(), crate::config::OutputConfig {
+                slug: "slow".to_string(),
+                cmd: Some("/bin/sleep".to_string()),
+                args: Some(vec!["1".to_string()]),
+                stream: true,
+                ..Default::default()
+            });
+        let app = Arc::new(app(config));
+
+        let slow_client = TestClient::new(app.clone());
+        let slow_request = tokio::spawn(async move {
+            let resp = slow_client.get("/output/slow").send().await;
+            resp.0.into_body().into_bytes().await.unwrap()
+        });
+
+        let client = TestClient::new(app);
+        let in_flight = || async {
+            let resp = client.get("/status").send().await;
+            let json = resp.json().await;
+            json.value()
+                .object()
+                .get("in_flight")
+                .object()
+                .get("total")
+                .i64()
+        };
+        let mut streaming = 0;
+        for _ in 0..100 {
+            streaming = in_flight().await;
+            if streaming > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(streaming, 1);
+
+        slow_request.await.unwrap();
+        assert_eq!(in_flight().await, 0);
+    }
 
-    tracing::info!("Starting server at {}", server_addr);
-    Server::new(TcpListener::bind(server_addr)).run(app).await
-}
+    #[tokio::test]
+    async fn test_output_job_lifecycle() {
+        let client = TestClient::new(app(create_test_config()));
 
-#[handler]
-async fn get_config(config: Data<&Arc<ResolvedConfig>>) -> Json<ResolvedConfig> {
-    Json(config.as_ref().clone())
-}
+        let resp = client.post("/output/echo-hello/async").send().await;
+        resp.assert_status(poem::http::StatusCode::ACCEPTED);
+        let body = resp.json().await;
+        let id = body.value().object().get("id").string().to_string();
 
-#[handler]
-async fn get_output(
-    config: Data<&Arc<ResolvedConfig>>,
-    Path(slug): Path<String>,
-) -> Result<Response> {
-    let output_config = config
-        .get_output_by_slug(&slug)
-        .ok_or_else(|| poem::Error::from_status(poem::http::StatusCode::NOT_FOUND))?;
+        let mut status = String::new();
+        for _ in 0..100 {
+            let resp = client.get(format!("/jobs/{id}")).send().await;
+            resp.assert_status_is_ok();
+            let json = resp.json().await;
+            let job = json.value().object();
+            status = job.get("status").string().to_string();
+            if status != "running" {
+                job.get("id").assert_string(&id);
+                job.get("slug").assert_string("echo-hello");
+                job.get("result").assert_string("hello world\n");
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(status, "succeeded");
+    }
 
-    let (cmd, args) = output_config.get_command_parts();
-    let mut command = Command::new(cmd);
-    command.args(args).current_dir(&config.data_dir);
+    #[tokio::test]
+    async fn test_output_job_unknown() {
+        let client = TestClient::new(app(create_test_config()));
+
+        let resp = client.post("/output/nonexistent/async").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
 
-    if let Some(modified_path) = get_modified_path(&config.data_dir) {
-        tracing::debug!("Modify PATH environment variable to: {}", modified_path);
-        command.env("PATH", modified_path);
+        let resp = client.get("/jobs/does-not-exist").send().await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
     }
 
-    let output = command.output().await.map_err(|e| {
-        poem::Error::from_string(
-            format!("Failed to execute command: {e}"),
-            poem::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )
-    })?;
+    #[tokio::test]
+    async fn test_get_output_span_fields() {
+        let (capture, _guard) = LogCapture::install();
 
-    // Always log stderr to server logs
-    if !output.stderr.is_empty() {
-        let stderr_str = String::from_utf8_lossy(&output.stderr);
-        if output.status.success() {
-            tracing::info!("Command stderr output:\n{}", stderr_str);
-        } else {
-            tracing::error!(
-                "Command failed with status: {}. Stderr:\n{}",
-                output.status,
-                stderr_str
-            );
-        }
+        let client = TestClient::new(app(create_test_config()));
+        client
+            .get("/output/echo-hello")
+            .send()
+            .await
+            .assert_status_is_ok();
+        client
+            .get("/output/nonexistent")
+            .send()
+            .await
+            .assert_status(poem::http::StatusCode::NOT_FOUND);
+
+        let logs = capture.contents();
+        assert!(logs.contains("output{slug=echo-hello status=200 duration_ms="));
+        assert!(logs.contains("output{slug=nonexistent status=404 duration_ms="));
     }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(poem::Error::from_string(
-            stderr.to_string(),
-            poem::http::StatusCode::INTERNAL_SERVER_ERROR,
-        ));
+    #[tokio::test]
+    async fn test_get_version() {
+        let client = TestClient::new(app(create_test_config()));
+        let resp = client.get("/version").send().await;
+        resp.assert_status_is_ok();
+        let json = resp.json().await;
+        let info = json.value().object();
+        info.get("version").assert_string(env!("CARGO_PKG_VERSION"));
+        assert!(!info.get("git").string().is_empty());
+        assert!(info.get("build_timestamp").i64() > 0);
+        assert!(info.get("rustc").string().starts_with("rustc"));
     }
 
-    let content = String::from_utf8(output.stdout.clone())
-        .unwrap_or_else(|_| String::from_utf8_lossy(&output.stdout).to_string());
+    #[tokio::test]
+    async fn test_request_id() {
+        let (capture, _guard) = LogCapture::install();
 
-    Ok(Response::builder()
-        .header("Content-Type", "text/plain; charset=utf-8")
-        .body(content))
-}
+        let client = TestClient::new(app(create_test_config()));
+        let resp = client.get("/output/echo-hello").send().await;
+        resp.assert_status_is_ok();
+        let generated = resp.0.headers().get("X-Request-Id").unwrap();
+        assert!(uuid::Uuid::parse_str(generated.to_str().unwrap()).is_ok());
 
-fn get_modified_path(data_dir: &std::path::Path) -> Option<String> {
-    let Ok(current_path) = std::env::var("PATH") else {
-        tracing::warn!("Failed to read PATH environment variable");
-        return None;
-    };
+        let resp = client
+            .get("/output/echo-hello")
+            .header("X-Request-Id", "trace-123")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        resp.assert_header("X-Request-Id", "trace-123");
 
-    let mut path_parts = Vec::new();
+        let resp = client
+            .get("/output/nonexistent")
+            .header("X-Request-Id", "trace-404")
+            .send()
+            .await;
+        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+        resp.assert_header("X-Request-Id", "trace-404");
 
-    // Try to add current executable directory to PATH
-    match std::env::current_exe() {
-        Ok(current_exe) => {
-            match current_exe.parent() {
-                Some(exe_dir) => {
-                    let exe_dir_str = exe_dir.to_string_lossy();
-                    // Add exe_dir if not already in PATH
-                    if !current_path.split(':').any(|p| p == exe_dir_str) {
-                        path_parts.push(exe_dir_str.to_string());
-                    }
-                }
-                None => {
-                    tracing::warn!("Failed to get parent directory of executable");
-                }
-            }
-        }
-        Err(e) => {
-            tracing::warn!("Failed to get current executable path: {}", e);
-        }
+        let logs = capture.contents();
+        assert!(logs.contains("request{request_id=trace-123}:output{slug=echo-hello status=200"));
+        assert!(logs.contains("request{request_id=trace-404}:output{slug=nonexistent status=404"));
     }
 
-    // Try to add data_dir to PATH
-    let data_dir_str = data_dir.to_string_lossy();
-    if !current_path.split(':').any(|p| p == data_dir_str) {
-        path_parts.push(data_dir_str.to_string());
+    async fn get_child_path(temp_dir: &TempDir, modify_path: bool) -> String {
+        let outputs = HashMap::from([("path".to_string(), crate::config::OutputConfig {
+            slug: "path".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec!["-c".to_string(), "echo $PATH".to_string()]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: temp_dir.path().to_path_buf(),
+            modify_path,
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/path").send().await;
+        resp.assert_status_is_ok();
+        resp.0.into_body().into_string().await.unwrap()
     }
 
-    // In case data directory might be the same as current executable directory
-    path_parts.dedup();
+    #[tokio::test]
+    async fn test_get_output_modify_path_enabled() {
+        let temp_dir = TempDir::new().unwrap();
+        let child_path = get_child_path(&temp_dir, true).await;
+        assert!(child_path
+            .trim_end()
+            .split(':')
+            .any(|p| p == temp_dir.path().to_str().unwrap()));
+    }
 
-    // Add the original PATH at the end
-    path_parts.push(current_path);
+    #[tokio::test]
+    async fn test_get_output_modify_path_disabled() {
+        let temp_dir = TempDir::new().unwrap();
+        let child_path = get_child_path(&temp_dir, false).await;
+        assert!(!child_path.is_empty());
+        assert!(!child_path.contains(temp_dir.path().to_str().unwrap()));
+    }
 
-    Some(path_parts.join(":"))
-}
+    #[tokio::test]
+    async fn test_reload_config() {
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("config.yaml");
+        let write_config = |greeting: &str| {
+            std::fs::write(
+                &path,
+                format!(
+                    "api_key: s3cret\noutputs:\n  - slug: greet\n    cmd: echo\n    args: [\"{greeting}\"]\n"
+                ),
+            )
+            .unwrap();
+        };
+        write_config("hello");
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+        let config_file = crate::config::ConfigFile {
+            path: path.clone(),
+            max_size: crate::config::DEFAULT_MAX_CONFIG_SIZE,
+        };
+        let config = config_file.load(data_dir.path().to_path_buf()).unwrap();
+        let client = TestClient::new(app(config));
+        let get_greeting = || async {
+            let resp = client
+                .get("/output/greet")
+                .header("Authorization", "Bearer s3cret")
+                .send()
+                .await;
+            resp.assert_status_is_ok();
+            resp.0.into_body().into_string().await.unwrap()
+        };
+        assert_eq!(get_greeting().await, "hello\n");
 
-    use poem::test::TestClient;
-    use tempfile::TempDir;
+        write_config("goodbye");
+        assert_eq!(get_greeting().await, "hello\n");
+        client
+            .post("/reload")
+            .send()
+            .await
+            .assert_status(poem::http::StatusCode::UNAUTHORIZED);
+        let resp = client
+            .post("/reload")
+            .header("Authorization", "Bearer s3cret")
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        assert_eq!(get_greeting().await, "goodbye\n");
 
-    use super::*;
+        // A broken config is rejected and the previous one stays live
+        std::fs::write(&path, "outputs: [").unwrap();
+        let resp = client
+            .post("/reload")
+            .header("Authorization", "Bearer s3cret")
+            .send()
+            .await;
+        resp.assert_status(poem::http::StatusCode::BAD_REQUEST);
+        assert_eq!(get_greeting().await, "goodbye\n");
+    }
 
-    fn create_test_config() -> ResolvedConfig {
-        let outputs = HashMap::from([
-            ("echo-hello".to_string(), crate::config::OutputConfig {
-                slug: "echo-hello".to_string(),
-                cmd: "/bin/echo".to_string(),
-                args: vec!["hello".to_string(), "world".to_string()],
-            }),
-            ("pwd".to_string(), crate::config::OutputConfig {
-                slug: "pwd".to_string(),
-                cmd: "/bin/pwd".to_string(),
-                args: vec![],
-            }),
-        ]);
+    #[tokio::test]
+    async fn test_reload_keeps_cache_of_unchanged_outputs() {
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("config.yaml");
+        let write_config = |changed_message: &str| {
+            std::fs::write(
+                &path,
+                format!(
+                    r#"api_key: s3cret
+outputs:
+  - slug: same
+    cmd: /bin/sh
+    args: ["-c", "date +%s%N >> same.runs; echo same"]
+    cache_ttl_secs: 60
+  - slug: changed
+    cmd: /bin/sh
+    args: ["-c", "date +%s%N >> changed.runs; echo {changed_message}"]
+    cache_ttl_secs: 60
+"#
+                ),
+            )
+            .unwrap();
+        };
+        let count_runs = |slug: &str| {
+            std::fs::read_to_string(data_dir.path().join(format!("{slug}.runs")))
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        write_config("before");
 
-        ResolvedConfig {
-            outputs,
-            data_dir: std::env::temp_dir(),
-        }
+        let config_file = crate::config::ConfigFile {
+            path: path.clone(),
+            max_size: crate::config::DEFAULT_MAX_CONFIG_SIZE,
+        };
+        let config = config_file.load(data_dir.path().to_path_buf()).unwrap();
+        let client = TestClient::new(app(config));
+        let get = |slug: &'static str| {
+            client
+                .get(format!("/output/{slug}"))
+                .header("Authorization", "Bearer s3cret")
+                .send()
+        };
+        get("same").await.assert_text("same\n").await;
+        get("changed").await.assert_text("before\n").await;
+
+        write_config("after");
+        client
+            .post("/reload")
+            .header("Authorization", "Bearer s3cret")
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        get("same").await.assert_text("same\n").await;
+        get("changed").await.assert_text("after\n").await;
+        assert_eq!(count_runs("same"), 1);
+        assert_eq!(count_runs("changed"), 2);
     }
 
     #[tokio::test]
-    async fn test_get_config_endpoint() {
-        let config = create_test_config();
-        let app = app(config.clone());
-        let client = TestClient::new(app);
+    async fn test_reload_clears_cache_when_command_settings_change() {
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("config.yaml");
+        let write_config = |strip_env: &str| {
+            std::fs::write(
+                &path,
+                format!(
+                    r#"api_key: s3cret
+strip_env: [{strip_env}]
+outputs:
+  - slug: home
+    cmd: /bin/sh
+    args: ["-c", "echo ${{HOME:-none}}"]
+    cache_ttl_secs: 60
+"#
+                ),
+            )
+            .unwrap();
+        };
+        write_config("");
 
-        let resp = client.get("/config").send().await;
-        resp.assert_status_is_ok();
-        resp.assert_content_type("application/json; charset=utf-8");
+        let config_file = crate::config::ConfigFile {
+            path: path.clone(),
+            max_size: crate::config::DEFAULT_MAX_CONFIG_SIZE,
+        };
+        let config = config_file.load(data_dir.path().to_path_buf()).unwrap();
+        let client = TestClient::new(app(config));
+        let get = || {
+            client
+                .get("/output/home")
+                .header("Authorization", "Bearer s3cret")
+                .send()
+        };
+        let home = get().await.0.into_body().into_string().await.unwrap();
+        assert_ne!(home, "none\n");
 
-        let returned_config: ResolvedConfig = resp.json().await.value().deserialize();
-        assert_eq!(returned_config.outputs.len(), config.outputs.len());
-        assert!(returned_config.outputs.contains_key("echo-hello"));
-        assert!(returned_config.outputs.contains_key("pwd"));
+        write_config("HOME");
+        client
+            .post("/reload")
+            .header("Authorization", "Bearer s3cret")
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        get().await.assert_text("none\n").await;
     }
 
+    #[cfg(unix)]
     #[tokio::test]
-    async fn test_get_output_existing_slug() {
-        let config = create_test_config();
-        let app = app(config);
-        let client = TestClient::new(app);
+    async fn test_reload_on_hangup() {
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("config.yaml");
+        std::fs::write(&path, "outputs:\n  - slug: before\n    cmd: echo\n").unwrap();
 
-        let resp = client.get("/output/echo-hello").send().await;
+        let config_file = crate::config::ConfigFile {
+            path: path.clone(),
+            max_size: crate::config::DEFAULT_MAX_CONFIG_SIZE,
+        };
+        let config = Arc::new(ArcSwap::from_pointee(
+            config_file.load(data_dir.path().to_path_buf()).unwrap(),
+        ));
+        let reloader =
+            spawn_reload_on_hangup(config.clone(), Arc::new(RuntimeState::default())).unwrap();
 
-        let status = resp.0.status();
-        let body = resp.0.into_body().into_string().await.unwrap();
+        std::fs::write(&path, "outputs:\n  - slug: after\n    cmd: echo\n").unwrap();
+        assert_eq!(unsafe { libc::kill(libc::getpid(), libc::SIGHUP) }, 0);
 
-        // Debug: Print status and body if not OK
-        if status != poem::http::StatusCode::OK {
-            panic!("Expected OK, got {status}: {body}");
+        for _ in 0..100 {
+            if config.load().get_output_by_slug("after").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
-
-        assert_eq!(status, poem::http::StatusCode::OK);
-        assert!(body.contains("hello world"));
+        assert!(config.load().get_output_by_slug("after").is_some());
+        assert!(config.load().get_output_by_slug("before").is_none());
+        reloader.abort();
     }
 
+    #[cfg(unix)]
     #[tokio::test]
-    async fn test_get_output_nonexistent_slug() {
-        let config = create_test_config();
-        let app = app(config);
-        let client = TestClient::new(app);
+    async fn test_reload_debounce() {
+        let (capture, _guard) = LogCapture::install();
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("config.yaml");
+        std::fs::write(
+            &path,
+            "reload_debounce_ms: 200\noutputs:\n  - slug: test\n    cmd: echo\n",
+        )
+        .unwrap();
 
-        let resp = client.get("/output/nonexistent").send().await;
-        resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+        let config_file = crate::config::ConfigFile {
+            path,
+            max_size: crate::config::DEFAULT_MAX_CONFIG_SIZE,
+        };
+        let config = Arc::new(ArcSwap::from_pointee(
+            config_file.load(data_dir.path().to_path_buf()).unwrap(),
+        ));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let triggers = futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx));
+        let reloader = tokio::spawn(reload_on(
+            triggers,
+            config,
+            Arc::new(RuntimeState::default()),
+        ));
+        let count_reloads = || capture.contents().matches("Reloaded config from").count();
+
+        for _ in 0..5 {
+            tx.send(()).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        for _ in 0..100 {
+            if count_reloads() > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(count_reloads(), 1);
+
+        // A trigger after the burst settled reloads again
+        tx.send(()).unwrap();
+        for _ in 0..100 {
+            if count_reloads() > 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(count_reloads(), 2);
+        reloader.abort();
     }
 
     #[tokio::test]
-    async fn test_get_output_with_pwd_command() {
-        let temp_dir = TempDir::new().unwrap();
-        let outputs = HashMap::from([("pwd".to_string(), crate::config::OutputConfig {
-            slug: "pwd".to_string(),
-            cmd: "/bin/pwd".to_string(),
-            args: vec![],
-        })]);
+    async fn test_reload_requires_api_key() {
+        let client = TestClient::new(app(create_test_config()));
+        client
+            .post("/reload")
+            .send()
+            .await
+            .assert_status(poem::http::StatusCode::NOT_FOUND);
+    }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_get_output_killed_by_signal() {
+        let outputs = HashMap::from([("suicide".to_string(), crate::config::OutputConfig {
+            slug: "suicide".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec!["-c".to_string(), "kill -TERM $$".to_string()]),
+            ..Default::default()
+        })]);
         let config = ResolvedConfig {
             outputs,
-            data_dir: temp_dir.path().to_path_buf(),
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
         };
 
-        let app = app(config);
-        let client = TestClient::new(app);
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/suicide").send().await;
+        resp.assert_status(poem::http::StatusCode::BAD_GATEWAY);
+        resp.assert_text("Command for output suicide was killed by signal 15 (SIGTERM)")
+            .await;
+    }
 
-        let resp = client.get("/output/pwd").send().await;
+    #[tokio::test]
+    async fn test_get_output_env() {
+        let outputs = HashMap::from([("region".to_string(), crate::config::OutputConfig {
+            slug: "region".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec!["-c".to_string(), "echo $AWS_REGION".to_string()]),
+            env: HashMap::from([("AWS_REGION".to_string(), "eu-west-1".to_string())]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
 
-        let status = resp.0.status();
-        let body = resp.0.into_body().into_string().await.unwrap();
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/region").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("eu-west-1\n").await;
+    }
 
-        if status != poem::http::StatusCode::OK {
-            panic!("Expected OK, got {status}: {body}");
-        }
+    #[tokio::test]
+    async fn test_get_output_command_path() {
+        let outputs = HashMap::from([("path".to_string(), crate::config::OutputConfig {
+            slug: "path".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec!["-c".to_string(), "echo $PATH".to_string()]),
+            ..Default::default()
+        })]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            modify_path: true,
+            command_path: Some("/opt/pinned/bin:/bin".to_string()),
+            ..Default::default()
+        };
 
-        assert_eq!(status, poem::http::StatusCode::OK);
-        assert!(body.contains(temp_dir.path().to_str().unwrap()));
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/path").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("/opt/pinned/bin:/bin\n").await;
     }
 
     #[tokio::test]
-    async fn test_get_output_invalid_command() {
-        let outputs = HashMap::from([("invalid".to_string(), crate::config::OutputConfig {
-            slug: "invalid".to_string(),
-            cmd: "this-command-does-not-exist-12345".to_string(),
-            args: vec![],
+    async fn test_get_output_strip_env() {
+        std::env::set_var("JUNCTION_TEST_STRIPPED_SECRET", "hunter2");
+        std::env::set_var("JUNCTION_TEST_KEPT", "visible");
+        let outputs = HashMap::from([("env".to_string(), crate::config::OutputConfig {
+            slug: "env".to_string(),
+            cmd: Some("/bin/sh".to_string()),
+            args: Some(vec![
+                "-c".to_string(),
+                "echo \"${JUNCTION_TEST_STRIPPED_SECRET-unset} $JUNCTION_TEST_KEPT\"".to_string(),
+            ]),
+            ..Default::default()
         })]);
-
         let config = ResolvedConfig {
             outputs,
             data_dir: std::env::temp_dir(),
+            strip_env: vec!["JUNCTION_TEST_STRIPPED_SECRET".to_string()],
+            ..Default::default()
         };
 
-        let app = app(config);
-        let client = TestClient::new(app);
-
-        let resp = client.get("/output/invalid").send().await;
-        resp.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/env").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("unset visible\n").await;
     }
 
     #[test]
@@ -313,12 +5142,81 @@ mod tests {
         std::env::set_var("PATH", original_path);
     }
 
+    #[tokio::test]
+    async fn test_get_output_shell_pipeline() {
+        let yaml = r#"
+outputs:
+  - slug: "piped"
+    shell: "echo hello world | tr a-z A-Z"
+  - slug: "positional"
+    shell: 'printf "%s:%s\n" "$0" "$1" | tr a-z A-Z'
+    args: ["it's safe; really"]
+"#;
+        let config = crate::config::Config::from_yaml_str(yaml).unwrap();
+        let config = ResolvedConfig::new(config, std::env::temp_dir()).unwrap();
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/piped").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("HELLO WORLD\n").await;
+
+        let resp = client.get("/output/positional").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("POSITIONAL:IT'S SAFE; REALLY\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_output_path_params() {
+        let echo = |slug: &str, arg: &str, path_params: Vec<String>| {
+            (slug.to_string(), crate::config::OutputConfig {
+                slug: slug.to_string(),
+                cmd: Some("echo".to_string()),
+                args: Some(vec![slug.to_string(), arg.to_string()]),
+                path_params,
+                ..Default::default()
+            })
+        };
+        let outputs = HashMap::from([
+            echo("user", "{id}", vec!["id".to_string()]),
+            echo("awk", "{print}", Vec::new()),
+        ]);
+        let config = ResolvedConfig {
+            outputs,
+            data_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+
+        let client = TestClient::new(app(config));
+        let resp = client.get("/output/user/42").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("user 42\n").await;
+
+        let resp = client.get("/output/user/%24%28id%29").send().await;
+        resp.assert_status(poem::http::StatusCode::BAD_REQUEST);
+        resp.assert_text("Invalid path parameter id: $(id)").await;
+
+        let resp = client.get("/output/user/async").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("user async\n").await;
+
+        for path in ["/output/user", "/output/user/42/43", "/output/awk/42"] {
+            let resp = client.get(path).send().await;
+            resp.assert_status(poem::http::StatusCode::NOT_FOUND);
+        }
+
+        // Braces in the args of outputs without path_params are left alone
+        let resp = client.get("/output/awk").send().await;
+        resp.assert_status_is_ok();
+        resp.assert_text("awk {print}\n").await;
+    }
+
     #[test]
     fn test_output_config_get_command_parts() {
         let output = crate::config::OutputConfig {
             slug: "test".to_string(),
-            cmd: "ls".to_string(),
-            args: vec!["-la".to_string(), "/tmp".to_string()],
+            cmd: Some("ls".to_string()),
+            args: Some(vec!["-la".to_string(), "/tmp".to_string()]),
+            ..Default::default()
         };
 
         let (cmd, args) = output.get_command_parts();